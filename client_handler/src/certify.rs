@@ -0,0 +1,48 @@
+use socket_stdinout::{
+    agent::{self, msg, Reader, CERT_REQUEST_EXT},
+    filter::AgentConn,
+    types::DynError,
+};
+use std::{
+    fs,
+    env,
+    os::unix::net::UnixStream,
+};
+use anyhow::anyhow;
+
+const SOCK_VAR: &str = "SSH_AUTH_SOCK";
+const USAGE: &str = "usage: client_handler certify <key.pub> [<principal>...]";
+
+/// Asks the vault for a certificate of the public key through the agent
+/// socket client_handler listens on, and writes it next to the key as
+/// <key>-cert.pub, where ssh picks it up.
+pub fn run(args: &[String]) -> DynError<()> {
+    let [path, principals @ ..] = args else {
+        return Err(anyhow!(USAGE).into());
+    };
+    let Some(stem) = path.strip_suffix(".pub") else {
+        return Err(anyhow!("Error: {path} isn't a .pub file").into());
+    };
+
+    let public_key = fs::read_to_string(path)?;
+    let mut request = vec![msg::EXTENSION];
+    agent::put_string(&mut request, CERT_REQUEST_EXT);
+    agent::put_string(&mut request, public_key.trim());
+    for principal in principals {
+        agent::put_string(&mut request, principal);
+    }
+
+    let sock = env::var(SOCK_VAR)?;
+    let mut agent = AgentConn::new(UnixStream::connect(&sock)?, Some(sock));
+    let reply = agent.call(&request)?;
+    if reply.first() != Some(&msg::SUCCESS) {
+        return Err(anyhow!(
+            "Error: the vault refused the certificate, see its audit log").into());
+    }
+
+    let cert = Reader::new(&reply[1..]).str()?.to_string();
+    let cert_path = format!("{stem}-cert.pub");
+    fs::write(&cert_path, format!("{cert}\n"))?;
+    println!("certificate written to {cert_path}");
+    return Ok(());
+}
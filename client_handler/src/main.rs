@@ -1,3 +1,4 @@
+mod certify;
 mod qrexec;
 
 use crate::qrexec::QRExecProc;
@@ -7,10 +8,16 @@ use socket_stdinout::{
     debug::append,
     ERR_LOG_DIR_NAME,
 };
+use std::env;
 
 const DEBUG_FNAME: &str = "Main";
 
 fn main() -> DynError<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "certify") {
+        return certify::run(&args[1..]);
+    }
+
     let qrexec = match QRExecProc::new() {
         Ok(qrexec) => qrexec,
         Err(e) => {
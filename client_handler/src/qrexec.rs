@@ -60,7 +60,14 @@ pub struct QRExecProc {
 
 impl QRExecProc {
     const VAULT_VM_NAME_ENV: &str = "SSH_VAULT_VM";
+    /// optional, sent as the service argument dom0 policy and the vault
+    /// pick a key set by: qubes.SplitSSHAgent+<arg>
+    const SERVICE_ARG_ENV: &str = "SSH_VAULT_ARG";
     const RPC_SERVICE_NAME: &str = "qubes.SplitSSHAgent";
+    /// qrexec's limit on the length of a service name with its argument
+    const MAX_SERVICE_LEN: usize = 64;
+    const SERVICE_ARG_ERR: &str =
+        "Error: SSH_VAULT_ARG has to be at most 44 letters, digits, -, _, . or +";
     const STDIN_ERR: &str = 
         "Error: failed to produce a stdin for qrexec child proc.";
     const STDOUT_ERR: &str = 
@@ -80,10 +87,12 @@ impl QRExecProc {
             }
         };
 
+        let service = Self::service_name()?;
+
         let mut child = DropChild(Command::new("qrexec-client-vm")
             .args([
                 &remote_vm, 
-                Self::RPC_SERVICE_NAME,
+                &service,
             ])
             .stdin(Stdio::piped()) 
             .stdout(Stdio::piped())
@@ -106,4 +115,25 @@ impl QRExecProc {
             stderr,
         });
     }
+
+    /// the RPC service name, with the argument from SSH_VAULT_ARG if set.
+    fn service_name() -> DynError<String> {
+        let arg = match env::var(Self::SERVICE_ARG_ENV) {
+            Ok(arg) if !arg.is_empty() => arg,
+            _ => return Ok(Self::RPC_SERVICE_NAME.to_string()),
+        };
+
+        let valid = arg.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.+".contains(c));
+        let service = format!("{}+{arg}", Self::RPC_SERVICE_NAME);
+        if !valid || service.len() > Self::MAX_SERVICE_LEN {
+            append(
+                Self::SERVICE_ARG_ERR,
+                DEBUG_FNAME,
+                ERR_LOG_DIR_NAME);
+            return Err(anyhow!(Self::SERVICE_ARG_ERR).into());
+        }
+
+        return Ok(service);
+    }
 }
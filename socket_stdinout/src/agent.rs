@@ -0,0 +1,239 @@
+#[cfg(test)]
+mod agent_tests;
+
+use crate::types::DynError;
+use anyhow::anyhow;
+
+/// ssh-agent protocol message numbers, draft-miller-ssh-agent.
+pub mod msg {
+    pub const FAILURE: u8 = 5;
+    pub const SUCCESS: u8 = 6;
+    /// protocol 1 leftover ssh-add -D still sends and ssh-agent handles
+    /// like REMOVE_ALL_IDENTITIES
+    pub const REMOVE_ALL_RSA_IDENTITIES: u8 = 9;
+    pub const REQUEST_IDENTITIES: u8 = 11;
+    pub const IDENTITIES_ANSWER: u8 = 12;
+    pub const SIGN_REQUEST: u8 = 13;
+    pub const SIGN_RESPONSE: u8 = 14;
+    pub const ADD_IDENTITY: u8 = 17;
+    pub const REMOVE_IDENTITY: u8 = 18;
+    pub const REMOVE_ALL_IDENTITIES: u8 = 19;
+    pub const ADD_SMARTCARD_KEY: u8 = 20;
+    pub const REMOVE_SMARTCARD_KEY: u8 = 21;
+    pub const LOCK: u8 = 22;
+    pub const UNLOCK: u8 = 23;
+    pub const ADD_ID_CONSTRAINED: u8 = 25;
+    pub const ADD_SMARTCARD_KEY_CONSTRAINED: u8 = 26;
+    pub const EXTENSION: u8 = 27;
+    pub const EXTENSION_FAILURE: u8 = 28;
+    pub const EXTENSION_RESPONSE: u8 = 29;
+
+    pub fn name(msg_type: u8) -> &'static str {
+        match msg_type {
+            FAILURE => "FAILURE",
+            SUCCESS => "SUCCESS",
+            REMOVE_ALL_RSA_IDENTITIES => "REMOVE_ALL_RSA_IDENTITIES",
+            REQUEST_IDENTITIES => "REQUEST_IDENTITIES",
+            IDENTITIES_ANSWER => "IDENTITIES_ANSWER",
+            SIGN_REQUEST => "SIGN_REQUEST",
+            SIGN_RESPONSE => "SIGN_RESPONSE",
+            ADD_IDENTITY => "ADD_IDENTITY",
+            REMOVE_IDENTITY => "REMOVE_IDENTITY",
+            REMOVE_ALL_IDENTITIES => "REMOVE_ALL_IDENTITIES",
+            ADD_SMARTCARD_KEY => "ADD_SMARTCARD_KEY",
+            REMOVE_SMARTCARD_KEY => "REMOVE_SMARTCARD_KEY",
+            LOCK => "LOCK",
+            UNLOCK => "UNLOCK",
+            ADD_ID_CONSTRAINED => "ADD_ID_CONSTRAINED",
+            ADD_SMARTCARD_KEY_CONSTRAINED => "ADD_SMARTCARD_KEY_CONSTRAINED",
+            EXTENSION => "EXTENSION",
+            EXTENSION_FAILURE => "EXTENSION_FAILURE",
+            EXTENSION_RESPONSE => "EXTENSION_RESPONSE",
+            _ => "UNKNOWN",
+        }
+    }
+}
+
+/// SIGN_REQUEST flags
+pub mod flags {
+    pub const RSA_SHA2_256: u32 = 1 << 1;
+    pub const RSA_SHA2_512: u32 = 1 << 2;
+}
+
+/// Extension the vault answers itself with a certificate for a key of
+/// the client domain: the contents are the public key in OpenSSH format
+/// followed by the principals asked for, none for every principal the
+/// domain may have. The reply is SUCCESS and the certificate, again in
+/// OpenSSH format.
+pub const CERT_REQUEST_EXT: &str = "cert-request@split-ssh";
+
+// in bytes
+pub const LENGTH_LEN: usize = 4;
+/// the same limit ssh-agent applies to a single message
+pub const MAX_MSG_LEN: usize = 256 * 1024;
+
+const TRUNCATED_ERR: &str = "Error: agent message truncated";
+const TOO_LONG_ERR: &str = "Error: agent message exceeds the maximum length";
+
+/// Removes the first complete message from buf and returns its body,
+/// i.e. the message type followed by the contents without the length.
+/// Returns None if buf doesn't hold a complete message yet.
+pub fn take_msg(buf: &mut Vec<u8>) -> DynError<Option<Vec<u8>>> {
+    if buf.len() < LENGTH_LEN {
+        return Ok(None);
+    }
+
+    let len = u32::from_be_bytes(buf[..LENGTH_LEN].try_into()?) as usize;
+    if len > MAX_MSG_LEN {
+        return Err(anyhow!(TOO_LONG_ERR).into());
+    }
+
+    if buf.len() < LENGTH_LEN + len {
+        return Ok(None);
+    }
+
+    let body = buf[LENGTH_LEN..(LENGTH_LEN + len)].to_vec();
+    buf.drain(..(LENGTH_LEN + len));
+    return Ok(Some(body));
+}
+
+/// Prefixes body with its length, producing a message ready for the wire.
+pub fn frame_msg(body: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(LENGTH_LEN + body.len());
+    put_u32(&mut framed, body.len() as u32);
+    framed.extend_from_slice(body);
+    return framed;
+}
+
+pub fn failure() -> Vec<u8> {
+    return vec![msg::FAILURE];
+}
+
+pub fn success() -> Vec<u8> {
+    return vec![msg::SUCCESS];
+}
+
+pub fn put_u8(buf: &mut Vec<u8>, val: u8) {
+    buf.push(val);
+}
+
+pub fn put_bool(buf: &mut Vec<u8>, val: bool) {
+    buf.push(val as u8);
+}
+
+pub fn put_u32(buf: &mut Vec<u8>, val: u32) {
+    buf.extend_from_slice(&val.to_be_bytes());
+}
+
+pub fn put_u64(buf: &mut Vec<u8>, val: u64) {
+    buf.extend_from_slice(&val.to_be_bytes());
+}
+
+pub fn put_string(buf: &mut Vec<u8>, val: impl AsRef<[u8]>) {
+    let val = val.as_ref();
+    put_u32(buf, val.len() as u32);
+    buf.extend_from_slice(val);
+}
+
+/// Cursor over the RFC 4251 encoded fields of an agent message.
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        return Self { buf, pos: 0 };
+    }
+
+    fn take(&mut self, len: usize) -> DynError<&'a [u8]> {
+        if self.buf.len() - self.pos < len {
+            return Err(anyhow!(TRUNCATED_ERR).into());
+        }
+
+        let bytes = &self.buf[self.pos..(self.pos + len)];
+        self.pos += len;
+        return Ok(bytes);
+    }
+
+    pub fn u8(&mut self) -> DynError<u8> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    pub fn bool(&mut self) -> DynError<bool> {
+        return Ok(self.u8()? != 0);
+    }
+
+    pub fn u32(&mut self) -> DynError<u32> {
+        return Ok(u32::from_be_bytes(self.take(4)?.try_into()?));
+    }
+
+    pub fn u64(&mut self) -> DynError<u64> {
+        return Ok(u64::from_be_bytes(self.take(8)?.try_into()?));
+    }
+
+    pub fn string(&mut self) -> DynError<&'a [u8]> {
+        let len = self.u32()? as usize;
+        return self.take(len);
+    }
+
+    /// a string field that has to be valid utf8
+    pub fn str(&mut self) -> DynError<&'a str> {
+        return Ok(std::str::from_utf8(self.string()?)?);
+    }
+
+    pub fn bytes(&mut self, len: usize) -> DynError<&'a [u8]> {
+        return self.take(len);
+    }
+
+    /// everything that hasn't been read yet
+    pub fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.buf[self.pos..];
+        self.pos = self.buf.len();
+        return rest;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.pos == self.buf.len();
+    }
+
+    /// how many bytes have been read so far
+    pub fn pos(&self) -> usize {
+        return self.pos;
+    }
+}
+
+/// A single key from an IDENTITIES_ANSWER.
+#[derive(Clone)]
+pub struct Identity {
+    pub blob: Vec<u8>,
+    pub comment: String,
+}
+
+/// Parses the contents of an IDENTITIES_ANSWER body, type byte included.
+pub fn parse_identities(body: &[u8]) -> DynError<Vec<Identity>> {
+    let mut reader = Reader::new(body);
+    if reader.u8()? != msg::IDENTITIES_ANSWER {
+        return Err(anyhow!("Error: not an identities answer").into());
+    }
+
+    let count = reader.u32()?;
+    let mut ids = Vec::new();
+    for _ in 0..count {
+        let blob = reader.string()?.to_vec();
+        let comment = String::from_utf8_lossy(reader.string()?).into_owned();
+        ids.push(Identity { blob, comment });
+    }
+
+    return Ok(ids);
+}
+
+pub fn encode_identities(ids: &[Identity]) -> Vec<u8> {
+    let mut body = vec![msg::IDENTITIES_ANSWER];
+    put_u32(&mut body, ids.len() as u32);
+    for id in ids {
+        put_string(&mut body, &id.blob);
+        put_string(&mut body, id.comment.as_bytes());
+    }
+    return body;
+}
@@ -0,0 +1,36 @@
+use super::{
+    Identity,
+    encode_identities,
+    parse_identities,
+    take_msg,
+    frame_msg,
+    msg::IDENTITIES_ANSWER,
+};
+
+#[test]
+fn take_msg_waits_for_full_message() {
+    let framed = frame_msg(&[IDENTITIES_ANSWER, 0, 0, 0, 0]);
+    let mut buf = framed[..6].to_vec();
+    assert!(take_msg(&mut buf).unwrap().is_none(),
+        "take_msg returned a message from a partial buffer");
+
+    buf.extend_from_slice(&framed[6..]);
+    buf.extend_from_slice(&framed);
+    let body = take_msg(&mut buf).unwrap().unwrap();
+    assert_eq!(body, &framed[4..]);
+    assert_eq!(buf, framed, "take_msg didn't leave the second message in place");
+}
+
+#[test]
+fn identities_round_trip() {
+    let ids = vec![
+        Identity { blob: vec![1, 2, 3], comment: "a@vault".to_string() },
+        Identity { blob: vec![4], comment: String::new() },
+    ];
+
+    let parsed = parse_identities(&encode_identities(&ids)).unwrap();
+    assert_eq!(parsed.len(), 2);
+    assert_eq!(parsed[0].blob, ids[0].blob);
+    assert_eq!(parsed[0].comment, ids[0].comment);
+    assert_eq!(parsed[1].blob, ids[1].blob);
+}
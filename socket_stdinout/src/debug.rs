@@ -27,7 +27,7 @@ macro_rules! err {
     };
 }
 
-fn get_xdg_state_dir(dir_name: impl std::fmt::Display) -> DynError<String> {
+pub fn get_xdg_state_dir(dir_name: impl std::fmt::Display) -> DynError<String> {
     const XDG_VAR: &str = "XDG_STATE_HOME";     
     const DEFAULT_VAR: &str = "HOME";
     const DEFAULT_POSTFIX: &str = /*$HOME*/".local/state";
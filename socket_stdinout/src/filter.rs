@@ -0,0 +1,223 @@
+use crate::{
+    KIB64,
+    agent,
+    conn_ssh_agent,
+    touts,
+    is_io_err_minor,
+    types::DynError,
+    msg_header::{
+        MsgHeader,
+        HEADER_LEN,
+        flags::*,
+        FLAGS_INDEX,
+    },
+};
+
+use std::{
+    os::unix::net::UnixStream,
+    io::{
+        self,
+        Read,
+        Write,
+        ErrorKind::{BrokenPipe, UnexpectedEof},
+    },
+};
+use anyhow::anyhow;
+
+const MAX_PAYLOAD: usize = KIB64 - HEADER_LEN;
+
+/// Inspects every ssh-agent request arriving from the client side before
+/// anything reaches the agent. The filter decides what, if anything, gets
+/// forwarded through the AgentConn and returns the reply for the client.
+pub trait AgentFilter {
+    /// msg and the returned reply are message bodies: the message type
+    /// followed by its contents, without the length prefix.
+    fn request(&mut self, agent: &mut AgentConn, msg: Vec<u8>) -> DynError<Vec<u8>>;
+
+    /// The client side accepted a new ssh-agent connection.
+    fn reconnect(&mut self);
+}
+
+/// An agent answering requests in process instead of behind a socket,
+/// served with handle_backend.
+pub trait AgentBackend {
+    /// msg and the returned reply are message bodies, as for
+    /// AgentConn::call.
+    fn call(&mut self, msg: &[u8]) -> DynError<Vec<u8>>;
+
+    /// The equivalent of a new connection to the agent.
+    fn reconnect(&mut self) -> DynError<()>;
+}
+
+enum Agent {
+    Socket {
+        stream: UnixStream,
+        /// the agent socket, found with find_agent when None
+        path: Option<String>,
+    },
+    Backend(Box<dyn AgentBackend>),
+}
+
+/// Request/response connection to the vault's ssh-agent.
+pub struct AgentConn(Agent);
+
+impl AgentConn {
+    pub fn new(stream: UnixStream, path: Option<String>) -> Self {
+        return Self(Agent::Socket { stream, path });
+    }
+
+    pub fn with_backend(backend: Box<dyn AgentBackend>) -> Self {
+        return Self(Agent::Backend(backend));
+    }
+
+    /// Replaces the connection with a fresh one to the ssh-agent.
+    pub fn reconnect(&mut self) -> DynError<()> {
+        match &mut self.0 {
+            Agent::Socket { stream, path } => {
+                *stream = connect(path.as_deref())?;
+                return Ok(());
+            }
+
+            Agent::Backend(backend) => return backend.reconnect(),
+        }
+    }
+
+    /// Sends a message body to the agent and returns the body of its reply.
+    /// If the agent went away since the last call the request is retried
+    /// once over a new connection.
+    pub fn call(&mut self, msg: &[u8]) -> DynError<Vec<u8>> {
+        let (stream, path) = match &mut self.0 {
+            Agent::Socket { stream, path } => (stream, path),
+            Agent::Backend(backend) => return backend.call(msg),
+        };
+
+        match exchange(stream, msg) {
+            Ok(reply) => return Ok(reply),
+
+            Err(ref e) if e.kind() == BrokenPipe || e.kind() == UnexpectedEof => {
+                *stream = connect(path.as_deref())?;
+                return Ok(exchange(stream, msg)?);
+            }
+
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn connect(path: Option<&str>) -> DynError<UnixStream> {
+    let sock = conn_ssh_agent(path)?;
+    touts(&sock)?;
+    return Ok(sock);
+}
+
+fn exchange(stream: &mut UnixStream, msg: &[u8]) -> Result<Vec<u8>, io::Error> {
+    stream.write_all(&agent::frame_msg(msg))?;
+    stream.flush()?;
+
+    let mut len = [0u8; agent::LENGTH_LEN];
+    stream.read_exact(&mut len)?;
+
+    let len = u32::from_be_bytes(len) as usize;
+    if len > agent::MAX_MSG_LEN {
+        return Err(io::Error::other("Error: agent reply exceeds the maximum length"));
+    }
+
+    let mut reply = vec![0u8; len];
+    stream.read_exact(&mut reply)?;
+    return Ok(reply);
+}
+
+/// Runs the vault side of the protocol one request at a time: frames from
+/// `read` are reassembled into agent messages, passed through the filter
+/// and the replies are framed back out through `written`. Returns once
+/// `read` reaches end of file.
+pub(crate) fn serve<T, U, F>(
+    mut agent: AgentConn,
+    mut written: T,
+    mut read: U,
+    mut filter: F,
+) -> DynError<()> where
+    T: Write,
+    U: Read,
+    F: AgentFilter,
+{
+    let mut pending = Vec::new();
+
+    loop {
+        let Some((flags, payload)) = read_frame(&mut read)? else {
+            return Ok(());
+        };
+
+        if flags == RECONN {
+            pending.clear();
+            agent.reconnect()?;
+            filter.reconnect();
+            continue;
+        }
+
+        pending.extend_from_slice(&payload);
+        while let Some(msg) = agent::take_msg(&mut pending)? {
+            let reply = filter.request(&mut agent, msg)?;
+            write_frames(&mut written, &agent::frame_msg(&reply))?;
+        }
+    }
+}
+
+/// Reads one frame, returns None on a clean end of file.
+fn read_frame(read: &mut impl Read) -> DynError<Option<(u8, Vec<u8>)>> {
+    let mut header = MsgHeader::new();
+    if !read_full(read, &mut header[..])? {
+        return Ok(None);
+    }
+
+    if header[FLAGS_INDEX] == RECONN {
+        return Ok(Some((RECONN, Vec::new())));
+    }
+
+    let len = (header.len() as usize).checked_sub(HEADER_LEN)
+        .ok_or(anyhow!("Error: frame length shorter than its header"))?;
+
+    if len > MAX_PAYLOAD {
+        return Err(anyhow!("Error: frame length exceeds the buffer size").into());
+    }
+
+    let mut payload = vec![0u8; len];
+    if !read_full(read, &mut payload)? {
+        return Err(anyhow!("Error: end of file inside of a frame").into());
+    }
+
+    return Ok(Some((header[FLAGS_INDEX], payload)));
+}
+
+/// returns false if read is at end of file before the first byte.
+fn read_full(read: &mut impl Read, buf: &mut [u8]) -> DynError<bool> {
+    let mut cursor = 0;
+    while cursor < buf.len() {
+        match read.read(&mut buf[cursor..]) {
+            Ok(0) if cursor == 0 => return Ok(false),
+
+            Ok(0) => return Err(anyhow!("Error: end of file inside of a frame").into()),
+
+            Ok(nb) => cursor += nb,
+
+            Err(ref e) if is_io_err_minor(e) => continue,
+
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    return Ok(true);
+}
+
+/// splits data into frames no larger than the other side's read buffer.
+fn write_frames(written: &mut impl Write, data: &[u8]) -> DynError<()> {
+    let mut header = MsgHeader::new();
+    for chunk in data.chunks(MAX_PAYLOAD) {
+        header.update((HEADER_LEN + chunk.len()) as u64, NONE);
+        written.write_all(&*header)?;
+        written.write_all(chunk)?;
+    }
+
+    written.flush()?;
+    return Ok(());
+}
@@ -1,6 +1,8 @@
 mod data;
 mod msg_header;
+pub mod agent;
 pub mod debug;
+pub mod filter;
 pub mod types;
 
 use types::DynError;
@@ -12,6 +14,7 @@ use msg_header::{
     FLAGS_INDEX,
 };
 use data::CRwLock;
+use filter::{AgentFilter, AgentConn, AgentBackend};
 
 use std::{
     fs,
@@ -35,7 +38,7 @@ use std::{
     },
     os::unix::{
         net::{UnixListener, UnixStream},
-        fs::PermissionsExt,
+        fs::{PermissionsExt, MetadataExt},
     },
     thread::{JoinHandle, self},
 };
@@ -60,7 +63,7 @@ pub struct SockStdInOutCon {
     new_stream: Arc<CRwLock<UnixStream, NUM_THREADS>>,
 }
 
-impl<'a> SockStdInOutCon {
+impl SockStdInOutCon {
     const DEBUG_FNAME: &'static str = "SockStdInOutCon";
     const SRFW_ERR: &'static str = "Error: SockReaderFdWriter failed to spawn";
     const SWFR_ERR: &'static str = "Error: SockWriterFdReader failed to spawn";
@@ -110,7 +113,7 @@ impl<'a> SockStdInOutCon {
         let sock_writer_fd_reader = {
             let swfr = SockWriterFdReader {
                 new_stream: new_stream.clone(),
-                stream: stream,
+                stream,
                 fd: read,
                 kill: kill.clone(),
                 model,
@@ -155,12 +158,7 @@ fn kill_thread(
 /// returns true if the error is Interrupted, WouldBlock, TimedOut, else returns false.
 #[inline]
 fn is_io_err_minor(err: &io::Error) -> bool {
-    match err.kind() {
-        Interrupted => true,
-        WouldBlock => true,
-        TimedOut => true, 
-        _ => false, 
-    }
+    return matches!(err.kind(), Interrupted | WouldBlock | TimedOut);
 }
 
 fn load_new_stream(
@@ -384,7 +382,7 @@ impl<T: Read + Send> SockWriterFdReader<T> {
     ) -> Frame {
         if *cursor >= KIB64 && *start_idx != 0 {
             buf.copy_within(*start_idx..*cursor, 0);
-            *cursor = *cursor - *start_idx;
+            *cursor -= *start_idx;
             *start_idx = 0; 
         }
 
@@ -444,7 +442,7 @@ impl<T: Read + Send> SockWriterFdReader<T> {
     /// calls replace on self.new_stream to ensure that both of the 
     /// IO manager threads get proper access. 
     fn reconn_ssh_agent(&mut self) -> DynError<()> {
-        let sock = conn_ssh_agent()?; 
+        let sock = conn_ssh_agent(None)?; 
         touts(&sock)?;
         self.new_stream.replace(sock)?;
         self.new_stream.count().fetch_sub(1, SeqCst);
@@ -488,17 +486,61 @@ const SOCK_VAR: &str = "SSH_AUTH_SOCK";
 const THREAD_ERR: &str = "Error: at least one of the threads failed";
 
 fn finish_check(conn: &SockStdInOutCon) -> bool {
-    if conn.sock_reader_fd_writer.is_finished() || 
-        conn.sock_writer_fd_reader.is_finished() 
-    { 
-        return true; 
-    } else {
-        return false;
+    return conn.sock_reader_fd_writer.is_finished()
+        || conn.sock_writer_fd_reader.is_finished();
+}
+
+/// The sockets an ssh-agent may be listening on, in the order they're
+/// tried: $SSH_AUTH_SOCK, then the systemd user ssh-agent, gcr,
+/// gnome-keyring and gpg-agent sockets in $XDG_RUNTIME_DIR, which
+/// defaults to /run/user/<uid>, and gpg-agent's socket in ~/.gnupg.
+/// qrexec services don't inherit the session's $SSH_AUTH_SOCK.
+pub fn agent_sockets() -> Vec<String> {
+    const RUNTIME_VAR: &str = "XDG_RUNTIME_DIR";
+    const RUNTIME_SOCKETS: [&str; 5] = [
+        "ssh-agent.socket",
+        "openssh_agent",
+        "gcr/ssh",
+        "keyring/ssh",
+        "gnupg/S.gpg-agent.ssh",
+    ];
+
+    let mut paths: Vec<String> = env::var(SOCK_VAR).ok()
+        .filter(|path| !path.is_empty())
+        .into_iter()
+        .collect();
+
+    let runtime_dir = env::var(RUNTIME_VAR).ok()
+        .or_else(|| {
+            let uid = fs::metadata("/proc/self").ok()?.uid();
+            return Some(format!("/run/user/{uid}"));
+        });
+    if let Some(dir) = runtime_dir {
+        paths.extend(RUNTIME_SOCKETS.iter().map(|sock| format!("{dir}/{sock}")));
+    }
+
+    if let Ok(home) = env::var("HOME") {
+        paths.push(format!("{home}/.gnupg/S.gpg-agent.ssh"));
     }
+    return paths;
 }
 
-fn conn_ssh_agent() -> DynError<UnixStream> {
-    let path = env::var(SOCK_VAR)?;
+/// The first of agent_sockets that accepts a connection.
+pub fn find_agent() -> Option<String> {
+    return agent_sockets().into_iter()
+        .find(|path| UnixStream::connect(path).is_ok());
+}
+
+/// connects to the agent socket at path, or to the one find_agent
+/// finds if None.
+fn conn_ssh_agent(path: Option<&str>) -> DynError<UnixStream> {
+    let path = match path {
+        Some(path) => path.to_string(),
+        None => find_agent().ok_or_else(|| anyhow!(
+            "Error: no ssh-agent found, $SSH_AUTH_SOCK isn't set and no agent \
+            listens on the usual sockets"
+        ))?,
+    };
     let sock = if fs::exists(&path)? {
         UnixStream::connect(&path)?
     } else {
@@ -509,14 +551,25 @@ fn conn_ssh_agent() -> DynError<UnixStream> {
     return Ok(sock);
 }
 
-pub struct SockStream(UnixStream);
+pub struct SockStream {
+    stream: UnixStream,
+    path: Option<String>,
+}
 
 impl SockStream {
     // SockStream is used on the vault side
     pub fn new() -> DynError<Self> {
-        let sock = conn_ssh_agent()?;
+        let sock = conn_ssh_agent(None)?;
         touts(&sock)?;
-        return Ok(Self(sock));
+        return Ok(Self { stream: sock, path: None });
+    }
+
+    /// Like new but connects to the agent socket at path instead of
+    /// the one in $SSH_AUTH_SOCK.
+    pub fn connect(path: &str) -> DynError<Self> {
+        let sock = conn_ssh_agent(Some(path))?;
+        touts(&sock)?;
+        return Ok(Self { stream: sock, path: Some(path.to_string()) });
     }
     
     pub fn handle_connections<T, U>(
@@ -527,7 +580,7 @@ impl SockStream {
         T: Write + Send + 'static,
         U: Read + Send + 'static, 
     {
-        let handle = SockStdInOutCon::spawn(self.0, written, read, Model::Server);
+        let handle = SockStdInOutCon::spawn(self.stream, written, read, Model::Server);
 
         loop {
             if finish_check(&handle) { 
@@ -535,6 +588,37 @@ impl SockStream {
             }
         } 
     }  
+
+    /// Like handle_connections but every request is passed through
+    /// filter instead of being copied to the ssh-agent verbatim.
+    pub fn handle_filtered<T, U, F>(
+        self,
+        written: T,
+        read: U,
+        filter: F,
+    ) -> DynError<()> where
+        T: Write,
+        U: Read,
+        F: AgentFilter,
+    {
+        let agent = AgentConn::new(self.stream, self.path);
+        return filter::serve(agent, written, read, filter);
+    }
+}
+
+/// Like SockStream::handle_filtered but the requests the filter passes
+/// on are answered by backend instead of an ssh-agent.
+pub fn handle_backend<T, U, F>(
+    written: T,
+    read: U,
+    filter: F,
+    backend: Box<dyn AgentBackend>,
+) -> DynError<()> where
+    T: Write,
+    U: Read,
+    F: AgentFilter,
+{
+    return filter::serve(AgentConn::with_backend(backend), written, read, filter);
 }
 
 pub struct SockListener(UnixListener);
@@ -618,7 +702,7 @@ impl Drop for SockListener {
             return;
         };
 
-        let Ok(fstat) = std::fs::exists(&path) else {
+        let Ok(fstat) = std::fs::exists(path) else {
             return;
         };
 
@@ -0,0 +1,205 @@
+#[cfg(test)]
+mod agents_tests;
+
+use crate::{
+    prompt,
+    state,
+    audit::timestamp,
+    config::{Config, Profile},
+};
+
+use socket_stdinout::{
+    ERR_LOG_DIR_NAME,
+    debug::append,
+    types::DynError,
+};
+use std::{
+    fs,
+    thread,
+    io::ErrorKind::NotFound,
+    os::unix::{fs::PermissionsExt, net::UnixStream},
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant, SystemTime},
+};
+use anyhow::anyhow;
+
+const AGENTS_DIR: &str = "agents";
+const LOG_FNAME: &str = "Agents";
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// how long a new ssh-agent gets to create its socket
+const START_TIMEOUT: Duration = Duration::from_secs(5);
+/// wait before starting an agent again after a failed start
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// The socket of the profile's managed agent in the state directory.
+pub fn socket(profile: &str) -> DynError<String> {
+    return state::path(&format!("{AGENTS_DIR}/{profile}.sock"));
+}
+
+/// The private key files in dir, anything but dotfiles and .pub files,
+/// sorted so they load in a stable order.
+pub fn key_files(dir: &str) -> DynError<Vec<String>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') || name.ends_with(".pub") || !entry.file_type()?.is_file() {
+            continue;
+        }
+        files.push(format!("{dir}/{name}"));
+    }
+    files.sort();
+    return Ok(files);
+}
+
+fn log(msg: &str) {
+    append(
+        &format!("{} {msg}\n", timestamp(SystemTime::now())),
+        LOG_FNAME,
+        ERR_LOG_DIR_NAME);
+}
+
+/// One profile's ssh-agent, None until it's started and after it died.
+struct Managed<'a> {
+    profile: &'a Profile,
+    socket: String,
+    child: Option<Child>,
+    /// an agent an earlier supervisor left running is listening on the
+    /// socket, it's used until it goes away
+    adopted: bool,
+    start_at: Instant,
+}
+
+impl Managed<'_> {
+    /// Starts the agent if it isn't running and its retry delay is over.
+    fn check(&mut self, askpass: Option<&str>) {
+        let name = &self.profile.name;
+        if let Some(child) = &mut self.child {
+            match child.try_wait() {
+                Ok(None) => return,
+                Ok(Some(status)) => log(&format!("profile {name}: ssh-agent exited, {status}")),
+                Err(e) => log(&format!("profile {name}: ssh-agent lost, {e}")),
+            }
+            self.child = None;
+        }
+
+        if self.child.is_none() && UnixStream::connect(&self.socket).is_ok() {
+            if !self.adopted {
+                log(&format!("profile {name}: reusing the ssh-agent on {}", self.socket));
+                self.adopted = true;
+            }
+            return;
+        }
+        if self.adopted {
+            log(&format!("profile {name}: reused ssh-agent went away"));
+            self.adopted = false;
+        }
+
+        if Instant::now() < self.start_at {
+            return;
+        }
+
+        match self.start(askpass) {
+            Ok(child) => {
+                log(&format!("profile {name}: ssh-agent {} on {}", child.id(), self.socket));
+                self.child = Some(child);
+            }
+            Err(e) => {
+                log(&format!(
+                    "profile {name}: {e}, retrying in {}s", RETRY_DELAY.as_secs()));
+                self.start_at = Instant::now() + RETRY_DELAY;
+            }
+        }
+    }
+
+    fn start(&self, askpass: Option<&str>) -> DynError<Child> {
+        match fs::remove_file(&self.socket) {
+            Ok(()) => (),
+            Err(e) if e.kind() == NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut child = Command::new("ssh-agent")
+            .args(["-D", "-a", &self.socket])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()?;
+
+        let deadline = Instant::now() + START_TIMEOUT;
+        while UnixStream::connect(&self.socket).is_err() {
+            if let Some(status) = child.try_wait()? {
+                return Err(anyhow!("Error: ssh-agent exited on start, {status}").into());
+            }
+            if Instant::now() > deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(anyhow!("Error: ssh-agent didn't create its socket").into());
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        // an agent without its keys is still worth keeping, the keys can
+        // be added by hand
+        if let Err(e) = self.load(askpass) {
+            log(&format!("profile {}: {e}", self.profile.name));
+        }
+        return Ok(child);
+    }
+
+    /// ssh-add's the profile's keys, passphrases are asked for with the
+    /// askpass program.
+    fn load(&self, askpass: Option<&str>) -> DynError<()> {
+        let dir = &self.profile.keys_dir;
+        let files = key_files(dir)
+            .map_err(|e| anyhow!("Error: can't read keys from {dir}: {e}"))?;
+        if files.is_empty() {
+            return Err(anyhow!("Error: no keys in {dir}").into());
+        }
+
+        let mut cmd = Command::new("ssh-add");
+        cmd.arg("-q");
+        if let Some(lifetime) = self.profile.lifetime {
+            cmd.args(["-t", &lifetime.to_string()]);
+        }
+        prompt::use_askpass(&mut cmd, askpass);
+
+        let status = cmd.args(&files)
+            .env("SSH_AUTH_SOCK", &self.socket)
+            .stdin(Stdio::null())
+            .status()?;
+        if !status.success() {
+            return Err(anyhow!("Error: ssh-add failed for keys in {dir}, {status}").into());
+        }
+        return Ok(());
+    }
+}
+
+/// Runs one ssh-agent per profile block until killed, loading the
+/// profile's keys into it and starting it again whenever it dies.
+pub fn supervise(config: &Config) -> DynError<()> {
+    if config.profiles.is_empty() {
+        return Err(anyhow!("Error: vault.conf has no profile blocks").into());
+    }
+
+    let dir = state::path(AGENTS_DIR)?;
+    fs::create_dir_all(&dir)?;
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+
+    let mut agents = Vec::new();
+    for profile in &config.profiles {
+        agents.push(Managed {
+            profile,
+            socket: socket(&profile.name)?,
+            child: None,
+            adopted: false,
+            start_at: Instant::now(),
+        });
+    }
+
+    loop {
+        for agent in &mut agents {
+            agent.check(config.askpass.as_deref());
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
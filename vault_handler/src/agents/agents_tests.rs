@@ -0,0 +1,50 @@
+use super::{Managed, key_files};
+use crate::{
+    config::Profile,
+    testing::TempDir,
+};
+use std::{
+    fs,
+    os::unix::net::UnixListener,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn key_files_skip_public_keys_and_dotfiles() {
+    let dir = TempDir::new("keys");
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    for name in ["id_rsa", "id_ed25519", "id_ed25519.pub", ".hidden"] {
+        fs::write(dir.join(name), "").unwrap();
+    }
+
+    let files = key_files(dir.path()).unwrap();
+    assert_eq!(files, [dir.join("id_ed25519"), dir.join("id_rsa")]);
+}
+
+#[test]
+fn agent_left_on_the_socket_is_reused() {
+    let dir = TempDir::new("agents");
+    let profile = Profile {
+        name: "deploy".to_string(),
+        keys_dir: dir.path().to_string(),
+        lifetime: None,
+    };
+    let socket = dir.join("deploy.sock");
+    let listener = UnixListener::bind(&socket).unwrap();
+
+    // no ssh-agent is started while the retry delay runs
+    let mut agent = Managed {
+        profile: &profile,
+        socket,
+        child: None,
+        adopted: false,
+        start_at: Instant::now() + Duration::from_secs(3600),
+    };
+    agent.check(None);
+    assert!(agent.adopted);
+    assert!(agent.child.is_none());
+
+    drop(listener);
+    agent.check(None);
+    assert!(!agent.adopted);
+}
@@ -0,0 +1,43 @@
+use crate::audit::timestamp;
+
+use socket_stdinout::{
+    ERR_LOG_DIR_NAME,
+    debug::append,
+};
+use std::{
+    thread,
+    time::SystemTime,
+    process::{Command, Stdio},
+};
+
+const ALERTS_FNAME: &str = "alerts.log";
+
+/// Writes the alert to alerts.log in the state directory and runs the
+/// configured alert-command with the event name, client domain and
+/// message as arguments. The request doesn't wait for the command.
+pub fn raise(command: Option<&str>, event: &str, domain: &str, msg: &str) {
+    append(
+        &format!("{} {event} {domain} {msg}\n", timestamp(SystemTime::now())),
+        ALERTS_FNAME,
+        ERR_LOG_DIR_NAME);
+
+    let Some(command) = command else {
+        return;
+    };
+
+    let spawned = Command::new(command)
+        .args([event, domain, msg])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn();
+
+    match spawned {
+        Ok(mut child) => {
+            thread::spawn(move || child.wait());
+        }
+        Err(e) => append(
+            &format!("{} alert-command {command}: {e}\n", timestamp(SystemTime::now())),
+            ALERTS_FNAME,
+            ERR_LOG_DIR_NAME),
+    }
+}
@@ -0,0 +1,274 @@
+#[cfg(test)]
+mod anomaly_tests;
+
+use crate::audit::{Audit, Record};
+
+use socket_stdinout::types::DynError;
+use std::{
+    fs,
+    process,
+    collections::{HashMap, HashSet},
+};
+
+/// the anomalies `anomaly-alerts` can pick from
+pub const KINDS: [&str; 4] = ["new-host", "new-domain", "burst", "odd-hour"];
+/// signatures the baseline needs before anything is flagged
+const LEARNING: u64 = 100;
+/// a domain's signatures in one minute are a burst past this many times
+/// its busiest minute so far, and never below BURST_MIN
+const BURST_FACTOR: u64 = 2;
+const BURST_MIN: u64 = 10;
+/// an hour holding less than 1/ODD_HOUR_SHARE of the signatures is odd
+const ODD_HOUR_SHARE: u64 = 100;
+/// the baseline as of an offset into the audit log, kept next to it so a
+/// new vault_handler doesn't learn from the whole log again
+const SNAPSHOT_FNAME: &str = "audit.baseline";
+/// bytes of the audit log learned from before the snapshot is rewritten
+const SNAPSHOT_EVERY: u64 = 256 * 1024;
+/// bytes of the audit log read into memory at a time
+const READ_CHUNK: u64 = 1024 * 1024;
+
+/// A signature a key makes, as it's recorded in the audit log.
+pub struct KeyUse<'a> {
+    /// the record's RFC 3339 timestamp
+    pub ts: &'a str,
+    pub key: &'a str,
+    /// the key's comment, or its fingerprint, for messages
+    pub key_name: &'a str,
+    pub domain: &'a str,
+    /// host key fingerprint of the session-bound destination
+    pub dest: Option<&'a str>,
+    /// its known_hosts name, for messages
+    pub dest_name: Option<&'a str>,
+}
+
+impl KeyUse<'_> {
+    /// "YYYY-MM-DDTHH:MM"
+    fn minute(&self) -> &str {
+        return self.ts.get(..16).unwrap_or_default();
+    }
+
+    fn hour(&self) -> Option<usize> {
+        return self.ts.get(11..13)?.parse().ok().filter(|hour| *hour < 24);
+    }
+}
+
+pub struct Anomaly {
+    /// one of KINDS, the alert's event
+    pub kind: &'static str,
+    pub msg: String,
+}
+
+/// A domain's signatures per minute.
+#[derive(Default)]
+struct Minutes {
+    /// the minute of the domain's last signature, and the signatures in it
+    last: String,
+    in_last: u64,
+    /// the most signatures in any minute before the last
+    busiest: u64,
+}
+
+/// What the vault's keys normally do, learned from the successful
+/// SIGN_REQUESTs in the audit log. Every vault_handler writes to the log,
+/// so the baseline catches up on it before each check.
+#[derive(Default)]
+pub struct Baseline {
+    /// bytes of the audit log learned from
+    read: u64,
+    /// where the snapshot on disk is at, None until it was looked for
+    saved: Option<u64>,
+    signatures: u64,
+    hosts: HashSet<String>,
+    /// key fingerprint and domain
+    pairs: HashSet<(String, String)>,
+    /// signatures per UTC hour
+    hours: [u64; 24],
+    minutes: HashMap<String, Minutes>,
+}
+
+impl Baseline {
+    /// Learns from the records the audit log gained since the last call,
+    /// starts over if the log was replaced. The first call starts from
+    /// the snapshot, if there is one.
+    pub fn catch_up(&mut self, audit: &Audit) -> DynError<()> {
+        let path = audit.path(SNAPSHOT_FNAME);
+        if self.saved.is_none() {
+            *self = fs::read_to_string(&path).ok()
+                .and_then(|text| Self::parse(&text))
+                .unwrap_or_default();
+            self.saved = Some(self.read);
+        }
+
+        loop {
+            let (records, read) = match audit.records_from(self.read, READ_CHUNK)? {
+                Some(read) => read,
+                None => {
+                    *self = Self { saved: Some(0), ..Self::default() };
+                    continue;
+                }
+            };
+            if read == self.read {
+                break;
+            }
+            self.learn(&records);
+            self.read = read;
+        }
+
+        if self.read >= self.saved.unwrap_or_default() + SNAPSHOT_EVERY {
+            // written whole and renamed, other vault_handlers may be
+            // reading it
+            let tmp = format!("{path}.{}", process::id());
+            fs::write(&tmp, self.to_text())?;
+            fs::rename(&tmp, &path)?;
+            self.saved = Some(self.read);
+        }
+        return Ok(());
+    }
+
+    /// The snapshot file's contents.
+    fn to_text(&self) -> String {
+        let hours: Vec<String> = self.hours.iter().map(u64::to_string).collect();
+        let mut text = format!(
+            "read {}\nsignatures {}\nhours {}\n", self.read, self.signatures, hours.join(" "));
+        for host in &self.hosts {
+            text += &format!("host {host}\n");
+        }
+        for (key, domain) in &self.pairs {
+            text += &format!("pair {key} {domain}\n");
+        }
+        for (domain, minutes) in &self.minutes {
+            text += &format!(
+                "minutes {domain} {} {} {}\n", minutes.last, minutes.in_last, minutes.busiest);
+        }
+        return text;
+    }
+
+    /// None if anything doesn't parse, the log is learned from again.
+    fn parse(text: &str) -> Option<Self> {
+        let mut baseline = Self::default();
+        for line in text.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[..] {
+                ["read", read] => baseline.read = read.parse().ok()?,
+                ["signatures", signatures] => baseline.signatures = signatures.parse().ok()?,
+                ["hours", ref hours @ ..] if hours.len() == 24 => {
+                    for (hour, count) in baseline.hours.iter_mut().zip(hours) {
+                        *hour = count.parse().ok()?;
+                    }
+                }
+                ["host", host] => {
+                    baseline.hosts.insert(host.to_string());
+                }
+                ["pair", key, domain] => {
+                    baseline.pairs.insert((key.to_string(), domain.to_string()));
+                }
+                ["minutes", domain, last, in_last, busiest] => {
+                    baseline.minutes.insert(domain.to_string(), Minutes {
+                        last: last.to_string(),
+                        in_last: in_last.parse().ok()?,
+                        busiest: busiest.parse().ok()?,
+                    });
+                }
+                _ => return None,
+            }
+        }
+        return Some(baseline);
+    }
+
+    pub fn learn(&mut self, records: &[Record]) {
+        for record in records {
+            if record.get_str("msg") != Some("SIGN_REQUEST")
+                || record.get_str("result") != Some("SIGN_RESPONSE")
+            {
+                continue;
+            }
+            let (Some(ts), Some(key), Some(domain)) =
+                (record.get_str("ts"), record.get_str("key"), record.get_str("domain"))
+            else {
+                continue;
+            };
+
+            let dest = record.get_str("dest");
+            self.learn_use(&KeyUse { ts, key, key_name: key, domain, dest, dest_name: None });
+        }
+    }
+
+    fn learn_use(&mut self, key_use: &KeyUse) {
+        self.signatures += 1;
+        if let Some(dest) = key_use.dest {
+            self.hosts.insert(dest.to_string());
+        }
+        self.pairs.insert((key_use.key.to_string(), key_use.domain.to_string()));
+        if let Some(hour) = key_use.hour() {
+            self.hours[hour] += 1;
+        }
+
+        let minutes = self.minutes.entry(key_use.domain.to_string()).or_default();
+        if minutes.last == key_use.minute() {
+            minutes.in_last += 1;
+        } else {
+            minutes.busiest = minutes.busiest.max(minutes.in_last);
+            minutes.last = key_use.minute().to_string();
+            minutes.in_last = 1;
+        }
+    }
+
+    /// The ways key_use differs from the baseline, of the kinds asked
+    /// for. Nothing is flagged while the baseline is still learning.
+    pub fn check(&self, key_use: &KeyUse, kinds: &[&str]) -> Vec<Anomaly> {
+        let mut anomalies = Vec::new();
+        let wanted = |kind: &str| kinds.contains(&kind);
+        if self.signatures < LEARNING {
+            return anomalies;
+        }
+
+        let new_host = key_use.dest.filter(|dest| !self.hosts.contains(*dest));
+        if let Some(dest) = new_host.filter(|_| wanted("new-host")) {
+            anomalies.push(Anomaly {
+                kind: "new-host",
+                msg: format!(
+                    "first signature for host {} ({dest}) with key {}",
+                    key_use.dest_name.unwrap_or(dest), key_use.key_name),
+            });
+        }
+
+        let pair = (key_use.key.to_string(), key_use.domain.to_string());
+        if wanted("new-domain") && !self.pairs.contains(&pair) {
+            anomalies.push(Anomaly {
+                kind: "new-domain",
+                msg: format!("key {} signs for this domain for the first time", key_use.key_name),
+            });
+        }
+
+        let (this_minute, busiest) = match self.minutes.get(key_use.domain) {
+            Some(minutes) if minutes.last == key_use.minute() =>
+                (minutes.in_last + 1, minutes.busiest),
+            Some(minutes) => (1, minutes.busiest.max(minutes.in_last)),
+            None => (1, 0),
+        };
+        // once a minute, when the threshold is crossed
+        if wanted("burst") && this_minute == (busiest * BURST_FACTOR).max(BURST_MIN) + 1 {
+            anomalies.push(Anomaly {
+                kind: "burst",
+                msg: format!(
+                    "{this_minute} signatures within the minute, the busiest minute so far \
+                    had {busiest}"),
+            });
+        }
+
+        let odd_hour = key_use.hour()
+            .filter(|hour| self.hours[*hour] * ODD_HOUR_SHARE < self.signatures);
+        if let Some(hour) = odd_hour.filter(|_| wanted("odd-hour")) {
+            anomalies.push(Anomaly {
+                kind: "odd-hour",
+                msg: format!(
+                    "signature with key {} at {hour:02}h UTC, {} of {} signatures so far \
+                    were made in that hour",
+                    key_use.key_name, self.hours[hour], self.signatures),
+            });
+        }
+
+        return anomalies;
+    }
+}
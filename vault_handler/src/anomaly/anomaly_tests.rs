@@ -0,0 +1,162 @@
+use super::{Baseline, KeyUse, KINDS, SNAPSHOT_FNAME};
+use crate::{
+    audit::{Audit, Record},
+    testing::TempDir,
+};
+use std::fs;
+
+fn signed(ts: &str, key: &str, domain: &str, dest: Option<&str>) -> Record {
+    let mut record = Record::new(domain, "SIGN_REQUEST");
+    record.set("ts", ts);
+    record.set("key", key);
+    record.set("dest", dest);
+    record.set("decision", "allow");
+    record.set("result", "SIGN_RESPONSE");
+    return record;
+}
+
+/// 120 signatures by key a for work on host h1, a few a day between
+/// 09h and 17h.
+fn office_hours() -> Vec<Record> {
+    return (0..120)
+        .map(|i| signed(
+            &format!("2026-03-{:02}T{:02}:{:02}:00Z", 1 + i / 9, 9 + i % 9, i % 60),
+            "SHA256:a", "work", Some("SHA256:h1")))
+        .collect();
+}
+
+fn key_use<'a>(ts: &'a str, key: &'a str, domain: &'a str, dest: Option<&'a str>) -> KeyUse<'a> {
+    return KeyUse { ts, key, key_name: key, domain, dest, dest_name: None };
+}
+
+fn learned(records: &[Record]) -> Baseline {
+    let mut baseline = Baseline::default();
+    baseline.learn(records);
+    return baseline;
+}
+
+fn kinds(baseline: &Baseline, key_use: &KeyUse) -> Vec<&'static str> {
+    return baseline.check(key_use, &KINDS).iter().map(|anomaly| anomaly.kind).collect();
+}
+
+#[test]
+fn nothing_while_learning() {
+    let baseline = learned(&office_hours()[..50]);
+    let anything = key_use("2026-04-01T03:00:00Z", "SHA256:b", "dev", Some("SHA256:h2"));
+    assert!(kinds(&baseline, &anything).is_empty());
+}
+
+#[test]
+fn usual_use_is_quiet() {
+    let baseline = learned(&office_hours());
+    let usual = key_use("2026-04-01T10:30:00Z", "SHA256:a", "work", Some("SHA256:h1"));
+    assert!(kinds(&baseline, &usual).is_empty());
+}
+
+#[test]
+fn flags_new_hosts_and_domains_once() {
+    let mut baseline = learned(&office_hours());
+    let new_host = key_use("2026-04-01T10:30:00Z", "SHA256:a", "work", Some("SHA256:h2"));
+    assert_eq!(kinds(&baseline, &new_host), ["new-host"]);
+    baseline.learn(&[signed(new_host.ts, "SHA256:a", "work", Some("SHA256:h2"))]);
+    assert!(kinds(&baseline, &new_host).is_empty());
+
+    let new_domain = key_use("2026-04-01T10:31:00Z", "SHA256:a", "dev", Some("SHA256:h1"));
+    assert_eq!(kinds(&baseline, &new_domain), ["new-domain"]);
+    baseline.learn(&[signed(new_domain.ts, "SHA256:a", "dev", Some("SHA256:h1"))]);
+    assert!(kinds(&baseline, &new_domain).is_empty());
+}
+
+#[test]
+fn flags_a_burst_once_a_minute() {
+    let mut baseline = learned(&office_hours());
+    let burst = key_use("2026-04-01T10:30:00Z", "SHA256:a", "work", Some("SHA256:h1"));
+    let mut flagged = Vec::new();
+    for i in 1..=20 {
+        if kinds(&baseline, &burst).contains(&"burst") {
+            flagged.push(i);
+        }
+        baseline.learn(&[signed(burst.ts, "SHA256:a", "work", Some("SHA256:h1"))]);
+    }
+    assert_eq!(flagged, [11]);
+
+    let next_minute = key_use("2026-04-01T10:31:00Z", "SHA256:a", "work", Some("SHA256:h1"));
+    assert!(kinds(&baseline, &next_minute).is_empty());
+}
+
+#[test]
+fn flags_odd_hours() {
+    let baseline = learned(&office_hours());
+    let night = key_use("2026-04-01T03:00:00Z", "SHA256:a", "work", Some("SHA256:h1"));
+    assert_eq!(kinds(&baseline, &night), ["odd-hour"]);
+}
+
+#[test]
+fn learns_only_from_signatures() {
+    let mut records = office_hours();
+    let mut denied = signed("2026-03-20T10:00:00Z", "SHA256:a", "work", Some("SHA256:h2"));
+    denied.set("decision", "deny");
+    denied.set("result", "FAILURE");
+    records.push(denied);
+    records.push(Record::new("work", "REQUEST_IDENTITIES"));
+
+    let baseline = learned(&records);
+    let new_host = key_use("2026-04-01T10:30:00Z", "SHA256:a", "work", Some("SHA256:h2"));
+    assert_eq!(kinds(&baseline, &new_host), ["new-host"]);
+}
+
+#[test]
+fn reports_only_the_kinds_asked_for() {
+    let baseline = learned(&office_hours());
+    let odd = key_use("2026-04-01T03:00:00Z", "SHA256:a", "dev", Some("SHA256:h2"));
+    let anomalies = baseline.check(&odd, &["odd-hour"]);
+    assert_eq!(anomalies.len(), 1);
+    assert_eq!(anomalies[0].kind, "odd-hour");
+    assert!(anomalies[0].msg.contains("03h UTC"));
+}
+
+fn audit_with(name: &str, records: Vec<Record>) -> (Audit, TempDir) {
+    let dir = TempDir::new(&format!("anomaly-{name}"));
+    let audit = Audit::with_dir(dir.path());
+    for record in records {
+        audit.write(record).unwrap();
+    }
+    return (audit, dir);
+}
+
+#[test]
+fn snapshot_round_trips() {
+    let baseline = learned(&office_hours());
+    let parsed = Baseline::parse(&baseline.to_text()).unwrap();
+    assert_eq!(parsed.to_text().lines().count(), baseline.to_text().lines().count());
+    assert_eq!(parsed.signatures, 120);
+    assert_eq!(parsed.hours, baseline.hours);
+    assert_eq!(parsed.pairs, baseline.pairs);
+    assert!(Baseline::parse("signatures many").is_none());
+}
+
+#[test]
+fn catches_up_from_the_snapshot() {
+    let (audit, _dir) = audit_with("snapshot", office_hours());
+    let mut baseline = Baseline::default();
+    baseline.catch_up(&audit).unwrap();
+    assert_eq!(baseline.signatures, 120);
+
+    // a snapshot that claims more signatures than the log holds shows
+    // which one the next vault_handler learned from
+    let snapshot = baseline.to_text().replace("signatures 120", "signatures 1000");
+    fs::write(audit.path(SNAPSHOT_FNAME), snapshot).unwrap();
+    audit.write(signed("2026-04-01T10:30:00Z", "SHA256:a", "work", Some("SHA256:h2"))).unwrap();
+
+    let mut resumed = Baseline::default();
+    resumed.catch_up(&audit).unwrap();
+    assert_eq!(resumed.signatures, 1001);
+    assert!(resumed.hosts.contains("SHA256:h2"));
+
+    // one from a log that was replaced since is ignored
+    let stale = baseline.to_text().replace(&format!("read {}", baseline.read), "read 99999999");
+    fs::write(audit.path(SNAPSHOT_FNAME), stale).unwrap();
+    let mut relearned = Baseline::default();
+    relearned.catch_up(&audit).unwrap();
+    assert_eq!(relearned.signatures, 121);
+}
@@ -0,0 +1,529 @@
+#[cfg(test)]
+mod audit_tests;
+
+use crate::encoding::{hex, escape};
+
+use socket_stdinout::{
+    ERR_LOG_DIR_NAME,
+    debug::get_xdg_state_dir,
+    types::DynError,
+};
+use std::{
+    fs,
+    io::{Read, Seek, SeekFrom, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use sha2::{Sha256, Digest};
+use anyhow::anyhow;
+
+const AUDIT_FNAME: &str = "audit.log";
+const HEAD_FNAME: &str = "audit.head";
+const CHECKPOINTS_FNAME: &str = "audit.checkpoints";
+/// every this many entries the head of the chain is appended to the
+/// checkpoints file.
+const CHECKPOINT_INTERVAL: u64 = 64;
+const GENESIS: &str =
+    "0000000000000000000000000000000000000000000000000000000000000000";
+const HASH_KEY: &str = ",\"hash\":\"";
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+    Str(String),
+    Num(u64),
+    Bool(bool),
+    Null,
+}
+
+impl From<&str> for Value {
+    fn from(val: &str) -> Self {
+        return Self::Str(val.to_string());
+    }
+}
+
+impl From<String> for Value {
+    fn from(val: String) -> Self {
+        return Self::Str(val);
+    }
+}
+
+impl From<u64> for Value {
+    fn from(val: u64) -> Self {
+        return Self::Num(val);
+    }
+}
+
+impl From<u32> for Value {
+    fn from(val: u32) -> Self {
+        return Self::Num(val as u64);
+    }
+}
+
+impl From<bool> for Value {
+    fn from(val: bool) -> Self {
+        return Self::Bool(val);
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(val: Option<T>) -> Self {
+        return match val {
+            Some(val) => val.into(),
+            None => Self::Null,
+        };
+    }
+}
+
+/// One audit log line: a flat JSON object whose fields keep the
+/// order they were set in.
+pub struct Record(Vec<(String, Value)>);
+
+impl Record {
+    pub fn new(domain: &str, msg_type: &str) -> Self {
+        let mut record = Self(Vec::new());
+        record.set("ts", timestamp(SystemTime::now()));
+        record.set("domain", domain);
+        record.set("msg", msg_type);
+        return record;
+    }
+
+    /// sets a field, replacing the value if it was already set.
+    pub fn set(&mut self, key: &str, val: impl Into<Value>) {
+        let val = val.into();
+        match self.0.iter_mut().find(|(k, _)| k == key) {
+            Some((_, old)) => *old = val,
+            None => self.0.push((key.to_string(), val)),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        return self.0.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+    }
+
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        return match self.get(key) {
+            Some(Value::Str(s)) => Some(s),
+            _ => None,
+        };
+    }
+
+    pub fn get_num(&self, key: &str) -> Option<u64> {
+        return match self.get(key) {
+            Some(Value::Num(n)) => Some(*n),
+            _ => None,
+        };
+    }
+
+    /// Parses a line written by to_json, only flat objects holding
+    /// strings, unsigned integers, booleans and nulls are understood.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut parser = JsonParser { chars: line.trim().chars().peekable() };
+        let mut fields = Vec::new();
+
+        parser.expect('{')?;
+        if parser.chars.peek() == Some(&'}') {
+            parser.chars.next();
+            return Some(Self(fields));
+        }
+
+        loop {
+            let key = parser.string()?;
+            parser.expect(':')?;
+            fields.push((key, parser.value()?));
+
+            match parser.chars.next()? {
+                ',' => continue,
+                '}' => break,
+                _ => return None,
+            }
+        }
+
+        if parser.chars.next().is_some() {
+            return None;
+        }
+        return Some(Self(fields));
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("{");
+        for (i, (key, val)) in self.0.iter().enumerate() {
+            if i != 0 {
+                out.push(',');
+            }
+
+            push_json_str(&mut out, key);
+            out.push(':');
+            match val {
+                Value::Str(s) => push_json_str(&mut out, s),
+                Value::Num(n) => out.push_str(&n.to_string()),
+                Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+                Value::Null => out.push_str("null"),
+            }
+        }
+        out.push('}');
+        return out;
+    }
+}
+
+struct JsonParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl JsonParser<'_> {
+    fn expect(&mut self, c: char) -> Option<()> {
+        return (self.chars.next()? == c).then_some(());
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.expect('"')?;
+        let mut out = String::new();
+        loop {
+            match self.chars.next()? {
+                '"' => return Some(out),
+                '\\' => match self.chars.next()? {
+                    'n' => out.push('\n'),
+                    'r' => out.push('\r'),
+                    't' => out.push('\t'),
+                    'u' => {
+                        let code: String = (0..4).filter_map(|_| self.chars.next()).collect();
+                        out.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                    }
+                    c => out.push(c),
+                },
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        match self.chars.peek()? {
+            '"' => return Some(Value::Str(self.string()?)),
+
+            c if c.is_ascii_digit() => {
+                let mut num = String::new();
+                while let Some(c) = self.chars.peek().filter(|c| c.is_ascii_digit()) {
+                    num.push(*c);
+                    self.chars.next();
+                }
+                return Some(Value::Num(num.parse().ok()?));
+            }
+
+            _ => {
+                let word: String = std::iter::from_fn(
+                    || self.chars.next_if(char::is_ascii_alphabetic)
+                ).collect();
+
+                return match word.as_str() {
+                    "true" => Some(Value::Bool(true)),
+                    "false" => Some(Value::Bool(false)),
+                    "null" => Some(Value::Null),
+                    _ => None,
+                };
+            }
+        }
+    }
+}
+
+fn push_json_str(out: &mut String, s: &str) {
+    out.push('"');
+    out.push_str(&escape(s));
+    out.push('"');
+}
+
+/// RFC 3339 UTC timestamp with second precision.
+pub fn timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, day_secs) = ((secs / 86400) as i64, secs % 86400);
+
+    // civil_from_days, http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    return format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day,
+        day_secs / 3600, day_secs / 60 % 60, day_secs % 60,
+    );
+}
+
+fn sha256_hex(data: &str) -> String {
+    return hex(&Sha256::digest(data.as_bytes()));
+}
+
+/// Appends records to audit.log inside of the split-ssh state directory.
+///
+/// Every entry carries its sequence number and the hash of the entry
+/// before it, and is itself terminated by the sha256 of everything
+/// preceding its hash field. audit.head holds the latest sequence number
+/// and hash, audit.checkpoints gets a copy of it every
+/// CHECKPOINT_INTERVAL entries so it can be shipped off the vault.
+#[derive(Clone)]
+pub struct Audit {
+    dir: String,
+}
+
+impl Audit {
+    pub fn new() -> DynError<Self> {
+        let dir = get_xdg_state_dir(ERR_LOG_DIR_NAME)?;
+        fs::create_dir_all(&dir)?;
+        return Ok(Self { dir });
+    }
+
+    #[cfg(test)]
+    pub fn with_dir(dir: &str) -> Self {
+        return Self { dir: dir.to_string() };
+    }
+
+    /// fname next to the audit log.
+    pub fn path(&self, fname: &str) -> String {
+        return format!("{}/{fname}", self.dir);
+    }
+
+    pub fn write(&self, record: Record) -> DynError<()> {
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(self.path(AUDIT_FNAME))?;
+
+        // other vault_handler processes append to the same chain
+        file.lock()?;
+
+        // audit.head is behind the log if a vault_handler died between
+        // the two writes, the log is behind audit.head if it was
+        // truncated, chaining on from whichever is further keeps the gap
+        // visible to verify
+        let (last, partial) = last_entry(&mut file)?;
+        let head = self.head()?;
+        let newest = match (last, head) {
+            (Some(last), Some(head)) => Some(if head.0 > last.0 { head } else { last }),
+            (last, head) => last.or(head),
+        };
+        let (seq, prev) = match newest {
+            Some((seq, hash)) => (seq + 1, hash),
+            None => (0, GENESIS.to_string()),
+        };
+
+        let mut chained = Record(vec![("seq".to_string(), Value::Num(seq))]);
+        chained.0.extend(record.0);
+        chained.set("prev", prev);
+
+        let body = chained.to_json();
+        let hash = sha256_hex(&body);
+        let mut line = format!("{}{HASH_KEY}{hash}\"}}\n", &body[..(body.len() - 1)]);
+        if partial {
+            // whatever was cut off stays on a line of its own
+            line.insert(0, '\n');
+        }
+
+        // a single write keeps the line whole even if a reader races us
+        file.write_all(line.as_bytes())?;
+
+        let head = format!("{seq} {hash}\n");
+        let tmp = self.path(&format!("{HEAD_FNAME}.tmp"));
+        fs::write(&tmp, &head)?;
+        fs::rename(&tmp, self.path(HEAD_FNAME))?;
+
+        if (seq + 1) % CHECKPOINT_INTERVAL == 0 {
+            fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(self.path(CHECKPOINTS_FNAME))?
+                .write_all(format!("{} {head}", timestamp(SystemTime::now())).as_bytes())?;
+        }
+
+        return Ok(());
+    }
+
+    fn head(&self) -> DynError<Option<(u64, String)>> {
+        let head = match fs::read_to_string(self.path(HEAD_FNAME)) {
+            Ok(head) => head,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        return Ok(Some(parse_head(&head)
+            .ok_or(anyhow!("Error: audit.head is malformed"))?));
+    }
+
+    /// The records in up to limit bytes of the log after the first from
+    /// bytes, oldest first, and the offset to read on from next time.
+    /// Lines that don't parse are skipped, a line still being written is
+    /// left for later. None if the log is shorter than from, it was
+    /// replaced.
+    pub fn records_from(&self, from: u64, limit: u64) -> DynError<Option<(Vec<Record>, u64)>> {
+        let mut file = match fs::File::open(self.path(AUDIT_FNAME)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound =>
+                return Ok(Some((Vec::new(), 0))),
+            Err(e) => return Err(e.into()),
+        };
+        if file.metadata()?.len() < from {
+            return Ok(None);
+        }
+
+        let mut log = Vec::new();
+        file.seek(SeekFrom::Start(from))?;
+        file.take(limit).read_to_end(&mut log)?;
+        let whole = match log.iter().rposition(|b| *b == b'\n') {
+            Some(end) => end + 1,
+            // a line longer than limit isn't a record, skip it
+            None if log.len() as u64 == limit => log.len(),
+            None => 0,
+        };
+        let records = String::from_utf8_lossy(&log[..whole])
+            .lines()
+            .filter_map(Record::parse)
+            .collect();
+        return Ok(Some((records, from + whole as u64)));
+    }
+
+    /// Walks the whole chain, returns the number of entries if nothing was
+    /// edited, reordered, removed or truncated, else a description of
+    /// every problem found.
+    pub fn verify(&self) -> Result<u64, Vec<String>> {
+        let mut problems = Vec::new();
+        let log = match fs::read_to_string(self.path(AUDIT_FNAME)) {
+            Ok(log) => log,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(vec![format!("{AUDIT_FNAME}: {e}")]),
+        };
+
+        let mut hashes = Vec::new();
+        let mut prev = GENESIS.to_string();
+        for (i, line) in log.lines().enumerate() {
+            let lnum = i + 1;
+            let expected_seq = hashes.len() as u64;
+
+            let Some((body, hash)) = split_hash(line) else {
+                problems.push(format!("line {lnum}: not an audit entry"));
+                continue;
+            };
+
+            if sha256_hex(&body) != hash {
+                problems.push(format!("line {lnum}: hash mismatch, entry was edited"));
+            }
+
+            let record = Record::parse(&body);
+            let seq = record.as_ref().and_then(|r| r.get_num("seq"));
+            let entry_prev = record.as_ref().and_then(|r| r.get_str("prev"));
+
+            if seq != Some(expected_seq) {
+                problems.push(format!(
+                    "line {lnum}: sequence {} where {expected_seq} was expected, \
+                    entries were removed or reordered",
+                    seq.map(|s| s.to_string()).unwrap_or("missing".to_string()),
+                ));
+            }
+
+            if entry_prev != Some(prev.as_str()) {
+                problems.push(format!(
+                    "line {lnum}: previous hash doesn't match line {}", lnum - 1));
+            }
+
+            prev = hash.to_string();
+            hashes.push(prev.clone());
+        }
+
+        let mut anchors = Vec::new();
+        match fs::read_to_string(self.path(HEAD_FNAME)) {
+            Ok(head) => match parse_head(&head) {
+                Some(head) => anchors.push((HEAD_FNAME.to_string(), head)),
+                None => problems.push(format!("{HEAD_FNAME}: malformed")),
+            },
+            Err(_) if hashes.is_empty() => (),
+            Err(e) => problems.push(format!("{HEAD_FNAME}: {e}")),
+        }
+
+        if let Ok(checkpoints) = fs::read_to_string(self.path(CHECKPOINTS_FNAME)) {
+            for (i, line) in checkpoints.lines().enumerate() {
+                let name = format!("{CHECKPOINTS_FNAME} line {}", i + 1);
+                match line.split_once(' ').and_then(|(_, head)| parse_head(head)) {
+                    Some(head) => anchors.push((name, head)),
+                    None => problems.push(format!("{name}: malformed")),
+                }
+            }
+        }
+
+        for (name, (seq, hash)) in anchors {
+            match hashes.get(seq as usize) {
+                Some(entry_hash) if *entry_hash == hash => (),
+
+                Some(_) => problems.push(format!(
+                    "{name}: hash of entry {seq} differs, the log was rewritten")),
+
+                None => problems.push(format!(
+                    "{name}: entry {seq} is missing, the log was truncated")),
+            }
+        }
+
+        if let Ok(Some((seq, _))) = self.head()
+            && seq + 1 < hashes.len() as u64
+        {
+            problems.push(format!(
+                "{AUDIT_FNAME}: {} entries follow the head at {seq}",
+                hashes.len() as u64 - (seq + 1)));
+        }
+
+        if problems.is_empty() {
+            return Ok(hashes.len() as u64);
+        } else {
+            return Err(problems);
+        }
+    }
+}
+
+/// Sequence number and hash of the last whole line of the log, None if
+/// there's none or it isn't an entry, and whether the log ends in an
+/// unfinished line.
+fn last_entry(file: &mut fs::File) -> DynError<(Option<(u64, String)>, bool)> {
+    let mut pos = file.metadata()?.len();
+    let mut tail = Vec::new();
+    let line = loop {
+        let start = pos.saturating_sub(4096);
+        let mut chunk = vec![0; (pos - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut chunk)?;
+        chunk.extend(tail);
+        (tail, pos) = (chunk, start);
+
+        let Some(end) = tail.iter().rposition(|b| *b == b'\n') else {
+            if pos == 0 {
+                return Ok((None, !tail.is_empty()));
+            }
+            continue;
+        };
+        match tail[..end].iter().rposition(|b| *b == b'\n') {
+            Some(newline) => break (newline + 1, end),
+            None if pos == 0 => break (0, end),
+            None => continue,
+        }
+    };
+
+    let partial = tail.last() != Some(&b'\n');
+    let line = String::from_utf8_lossy(&tail[line.0..line.1]);
+    let entry = split_hash(&line).and_then(|(body, hash)| {
+        let seq = Record::parse(&body)?.get_num("seq")?;
+        return Some((seq, hash.to_string()));
+    });
+    return Ok((entry, partial));
+}
+
+/// "<seq> <hash>"
+fn parse_head(head: &str) -> Option<(u64, String)> {
+    let (seq, hash) = head.trim().split_once(' ')?;
+    return Some((seq.parse().ok()?, hash.to_string()));
+}
+
+/// splits an entry into the hashed body and the hash.
+fn split_hash(line: &str) -> Option<(String, &str)> {
+    let idx = line.rfind(HASH_KEY)?;
+    let hash = line[(idx + HASH_KEY.len())..].strip_suffix("\"}")?;
+    return Some((format!("{}}}", &line[..idx]), hash));
+}
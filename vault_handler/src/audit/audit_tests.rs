@@ -0,0 +1,131 @@
+use super::{
+    Audit,
+    Record,
+    Value,
+    AUDIT_FNAME,
+};
+use crate::{
+    encoding::escape,
+    testing::TempDir,
+};
+use std::{
+    fs,
+    io::Write,
+};
+
+fn audit_with(name: &str, entries: usize) -> (Audit, String, TempDir) {
+    let dir = TempDir::new(&format!("audit-{name}"));
+    let audit = Audit::with_dir(dir.path());
+    for i in 0..entries {
+        let mut record = Record::new("work", "SIGN_REQUEST");
+        record.set("n", i as u64);
+        audit.write(record).unwrap();
+    }
+
+    let log = dir.join(AUDIT_FNAME);
+    return (audit, log, dir);
+}
+
+#[test]
+fn record_json_round_trip() {
+    let mut record = Record::new("wo\"rk\n", "SIGN_REQUEST");
+    record.set("flags", 4u32);
+    record.set("forwarded", true);
+    record.set("dest", None::<String>);
+
+    let parsed = Record::parse(&record.to_json()).unwrap();
+    assert_eq!(parsed.get_str("domain"), Some("wo\"rk\n"));
+    assert_eq!(parsed.get_num("flags"), Some(4));
+    assert_eq!(parsed.get("forwarded"), Some(&Value::Bool(true)));
+    assert_eq!(parsed.get("dest"), Some(&Value::Null));
+}
+
+#[test]
+fn intact_chain_verifies() {
+    let (audit, _, _dir) = audit_with("intact", 70);
+    assert_eq!(audit.verify(), Ok(70));
+}
+
+#[test]
+fn edited_entry_is_detected() {
+    let (audit, log, _dir) = audit_with("edited", 5);
+    let edited = fs::read_to_string(&log).unwrap().replacen("\"n\":2", "\"n\":9", 1);
+    fs::write(&log, edited).unwrap();
+    assert!(audit.verify().is_err(), "an edited entry passed verification");
+}
+
+#[test]
+fn reordered_entries_are_detected() {
+    let (audit, log, _dir) = audit_with("reordered", 5);
+    let mut lines: Vec<String> = fs::read_to_string(&log).unwrap()
+        .lines().map(str::to_string).collect();
+    lines.swap(1, 2);
+    fs::write(&log, lines.join("\n") + "\n").unwrap();
+    assert!(audit.verify().is_err(), "reordered entries passed verification");
+}
+
+#[test]
+fn truncated_log_is_detected() {
+    let (audit, log, _dir) = audit_with("truncated", 5);
+    let log_data = fs::read_to_string(&log).unwrap();
+    let kept: Vec<&str> = log_data.lines().take(3).collect();
+    fs::write(&log, kept.join("\n") + "\n").unwrap();
+    assert!(audit.verify().is_err(), "a truncated log passed verification");
+}
+
+#[test]
+fn chain_goes_on_from_the_log_when_the_head_is_behind() {
+    let (audit, log, _dir) = audit_with("head-behind", 3);
+    let head = audit.path("audit.head");
+    let old_head = fs::read_to_string(&head).unwrap();
+    audit.write(Record::new("work", "SIGN_REQUEST")).unwrap();
+    // as if the writer died before replacing the head
+    fs::write(&head, old_head).unwrap();
+    // and in the middle of the next line
+    fs::OpenOptions::new().append(true).open(&log).unwrap().write_all(b"{\"seq\":4").unwrap();
+
+    audit.write(Record::new("work", "SIGN_REQUEST")).unwrap();
+    let problems = audit.verify().unwrap_err();
+    assert_eq!(problems, ["line 5: not an audit entry"]);
+    let last = fs::read_to_string(&log).unwrap().lines().last().map(Record::parse);
+    assert_eq!(last.flatten().unwrap().get_num("seq"), Some(4));
+}
+
+#[test]
+fn records_are_read_on_from_an_offset() {
+    let (audit, log, _dir) = audit_with("records", 3);
+    let (records, read) = audit.records_from(0, u64::MAX).unwrap().unwrap();
+    assert_eq!(records.len(), 3);
+
+    audit.write(Record::new("work", "SIGN_REQUEST")).unwrap();
+    fs::OpenOptions::new().append(true).open(&log).unwrap()
+        .write_all(b"{\"seq\":4,\"ts\":").unwrap();
+    let (records, read) = audit.records_from(read, u64::MAX).unwrap().unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].get_num("seq"), Some(3));
+    assert_eq!(audit.records_from(read, u64::MAX).unwrap().unwrap().0.len(), 0);
+
+    assert!(audit.records_from(1 << 20, u64::MAX).unwrap().is_none());
+}
+
+#[test]
+fn records_are_read_in_chunks() {
+    let (audit, log, _dir) = audit_with("chunks", 3);
+    let first_line = fs::read_to_string(&log).unwrap().find('\n').unwrap() as u64 + 1;
+
+    // only whole lines count, a chunk ending inside one stops before it
+    let (records, read) = audit.records_from(0, first_line + 10).unwrap().unwrap();
+    assert_eq!((records.len(), read), (1, first_line));
+    let (records, _) = audit.records_from(read, u64::MAX).unwrap().unwrap();
+    assert_eq!(records.len(), 2);
+
+    // a chunk without any end of line is skipped whole
+    let (records, read) = audit.records_from(0, 10).unwrap().unwrap();
+    assert_eq!((records.len(), read), (0, 10));
+}
+
+#[test]
+fn client_text_is_escaped() {
+    assert_eq!(escape("git\n2026-01-01T00:00:00Z fake"), "git\\n2026-01-01T00:00:00Z fake");
+    assert_eq!(escape("a\"b\\c\u{1b}"), "a\\\"b\\\\c\\u001b");
+}
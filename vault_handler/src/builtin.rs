@@ -0,0 +1,249 @@
+#[cfg(test)]
+mod builtin_tests;
+
+use crate::{
+    agents::key_files,
+    audit::timestamp,
+    keystore::Keystore,
+};
+
+use socket_stdinout::{
+    ERR_LOG_DIR_NAME,
+    agent::{self, msg, flags, Identity, Reader},
+    debug::append,
+    filter::AgentBackend,
+    types::DynError,
+};
+use std::{
+    path::Path,
+    time::{Duration, SystemTime},
+};
+use ssh_key::{
+    Algorithm,
+    EcdsaCurve,
+    HashAlg,
+    Mpint,
+    PrivateKey,
+    PublicKey,
+    Signature,
+    private::{KeypairData, RsaKeypair},
+    sha2::{Sha256, Sha512},
+};
+use signature::{Signer, SignatureEncoding, Verifier};
+use anyhow::anyhow;
+
+const LOG_FNAME: &str = "Builtin";
+const SESSION_BIND_EXT: &str = "session-bind@openssh.com";
+
+fn log(msg: &str) {
+    append(
+        &format!("{} {msg}\n", timestamp(SystemTime::now())),
+        LOG_FNAME,
+        ERR_LOG_DIR_NAME);
+}
+
+/// ssh-key's own conversion passes p twice instead of p and q.
+fn rsa_key(keypair: &RsaKeypair) -> DynError<rsa::RsaPrivateKey> {
+    let uint = |mpint: &Mpint| -> DynError<rsa::BigUint> {
+        let bytes = mpint.as_positive_bytes()
+            .ok_or(anyhow!("Error: negative RSA key component"))?;
+        return Ok(rsa::BigUint::from_bytes_be(bytes));
+    };
+
+    let (public, private) = (&keypair.public, &keypair.private);
+    return Ok(rsa::RsaPrivateKey::from_components(
+        uint(&public.n)?,
+        uint(&public.e)?,
+        uint(&private.d)?,
+        vec![uint(&private.p)?, uint(&private.q)?],
+    )?);
+}
+
+/// The key types sign_with can sign with, see the ssh-key features.
+fn supported(algorithm: &Algorithm) -> bool {
+    return matches!(algorithm,
+        Algorithm::Ed25519
+        | Algorithm::Rsa { .. }
+        | Algorithm::Ecdsa { curve: EcdsaCurve::NistP256 | EcdsaCurve::NistP384 });
+}
+
+/// None for signatures the built-in agent doesn't make.
+pub fn sign_with(private: &PrivateKey, data: &[u8], flags: u32) -> DynError<Option<Signature>> {
+    let signature = match private.key_data() {
+        KeypairData::Rsa(keypair) if flags & flags::RSA_SHA2_512 != 0 => {
+            let signing = rsa::pkcs1v15::SigningKey::<Sha512>::new(rsa_key(keypair)?);
+            let sig = signing.try_sign(data)?.to_vec();
+            Signature::new(Algorithm::Rsa { hash: Some(HashAlg::Sha512) }, sig)?
+        }
+
+        KeypairData::Rsa(keypair) if flags & flags::RSA_SHA2_256 != 0 => {
+            let signing = rsa::pkcs1v15::SigningKey::<Sha256>::new(rsa_key(keypair)?);
+            let sig = signing.try_sign(data)?.to_vec();
+            Signature::new(Algorithm::Rsa { hash: Some(HashAlg::Sha256) }, sig)?
+        }
+
+        // no SHA-1 ssh-rsa signatures, `rsa-sha1 upgrade` still works
+        KeypairData::Rsa(_) => return Ok(None),
+
+        _ => private.try_sign(data)?,
+    };
+    return Ok(Some(signature));
+}
+
+struct Key {
+    private: PrivateKey,
+    /// the public key blob as listed in IDENTITIES_ANSWER
+    blob: Vec<u8>,
+    comment: String,
+}
+
+/// The built-in agent: signs with the OpenSSH private keys in a
+/// directory of the vault, ed25519, ECDSA P-256/P-384 and RSA. Keys with
+/// a passphrase are listed right away and decrypted in the keystore on
+/// first use. It answers REQUEST_IDENTITIES, SIGN_REQUEST and
+/// session-bind, everything else gets SSH_AGENT_FAILURE.
+pub struct Builtin {
+    dir: String,
+    keys: Vec<Key>,
+    keystore: Keystore,
+}
+
+impl Builtin {
+    pub fn load(
+        dir: &str,
+        domain: &str,
+        askpass: Option<&str>,
+        idle: Duration,
+    ) -> DynError<Self> {
+        let mut builtin = Self {
+            dir: dir.to_string(),
+            keys: Vec::new(),
+            keystore: Keystore::new(domain, askpass, idle)?,
+        };
+        builtin.reconnect()?;
+        return Ok(builtin);
+    }
+
+    /// Keys that can't be used are logged and left out.
+    fn read_keys(dir: &str) -> DynError<Vec<Key>> {
+        let mut keys = Vec::new();
+        for path in key_files(dir)? {
+            let private = match PrivateKey::read_openssh_file(Path::new(&path)) {
+                Ok(private) => private,
+                Err(e) => {
+                    log(&format!("{path}: {e}, skipped"));
+                    continue;
+                }
+            };
+
+            if !supported(&private.algorithm()) {
+                log(&format!("{path}: {} keys aren't supported, skipped", private.algorithm()));
+                continue;
+            }
+
+            let blob = private.public_key().to_bytes()?;
+            let comment = match private.comment() {
+                // an encrypted key's comment is encrypted too
+                "" => PublicKey::read_openssh_file(Path::new(&format!("{path}.pub")))
+                    .map(|public| public.comment().to_string())
+                    .unwrap_or_default(),
+                comment => comment.to_string(),
+            };
+            keys.push(Key { private, blob, comment });
+        }
+        return Ok(keys);
+    }
+
+    fn identities(&self) -> Vec<u8> {
+        let ids: Vec<Identity> = self.keys.iter()
+            .map(|key| Identity {
+                blob: key.blob.clone(),
+                comment: key.comment.clone(),
+            })
+            .collect();
+        return agent::encode_identities(&ids);
+    }
+
+    fn sign(&mut self, msg: &[u8]) -> DynError<Vec<u8>> {
+        let mut reader = Reader::new(&msg[1..]);
+        let blob = reader.string()?;
+        let data = reader.string()?;
+        let flags = reader.u32()?;
+
+        let Some(key) = self.keys.iter().find(|key| key.blob == blob) else {
+            return Ok(agent::failure());
+        };
+
+        let signed = if key.private.is_encrypted() {
+            let encrypted: Vec<(&[u8], &PrivateKey)> = self.keys.iter()
+                .filter(|key| key.private.is_encrypted())
+                .map(|key| (key.blob.as_slice(), &key.private))
+                .collect();
+            self.keystore.with_key(blob, &encrypted, |private| sign_with(private, data, flags))?
+                .transpose()
+                .map(Option::flatten)
+        } else {
+            sign_with(&key.private, data, flags)
+        };
+
+        // a failed signature is the client's failure, not the vault's
+        let signature = match signed {
+            Ok(Some(signature)) => signature,
+            Ok(None) => return Ok(agent::failure()),
+            Err(e) => {
+                log(&format!("{}: can't sign, {e}", key.comment));
+                return Ok(agent::failure());
+            }
+        };
+
+        let mut encoded = Vec::new();
+        agent::put_string(&mut encoded, signature.algorithm().as_str());
+        agent::put_string(&mut encoded, signature.as_bytes());
+
+        let mut reply = vec![msg::SIGN_RESPONSE];
+        agent::put_string(&mut reply, &encoded);
+        return Ok(reply);
+    }
+
+    /// Checks the host key's signature of the session id the way
+    /// ssh-agent does, the vault only records binds that succeed.
+    fn extension(&self, msg: &[u8]) -> DynError<Vec<u8>> {
+        let mut reader = Reader::new(&msg[1..]);
+        if reader.string()? != SESSION_BIND_EXT.as_bytes() {
+            return Ok(agent::failure());
+        }
+
+        let mut bind = || -> DynError<()> {
+            let host_key = PublicKey::from_bytes(reader.string()?)?;
+            let session_id = reader.string()?;
+            let signature = Signature::try_from(reader.string()?)?;
+            host_key.key_data().verify(session_id, &signature)?;
+            return Ok(());
+        };
+
+        // malformed binds fail like ones with a bad signature
+        match bind() {
+            Ok(()) => return Ok(agent::success()),
+            Err(_) => return Ok(agent::failure()),
+        }
+    }
+}
+
+impl AgentBackend for Builtin {
+    fn call(&mut self, msg: &[u8]) -> DynError<Vec<u8>> {
+        match msg.first().copied() {
+            Some(msg::REQUEST_IDENTITIES) => return Ok(self.identities()),
+            Some(msg::SIGN_REQUEST) => return self.sign(msg),
+            Some(msg::EXTENSION) => return self.extension(msg),
+            _ => return Ok(agent::failure()),
+        }
+    }
+
+    /// Reads the key directory again, so keys added or removed there
+    /// count from the client's next connection on.
+    fn reconnect(&mut self) -> DynError<()> {
+        self.keys = Self::read_keys(&self.dir)
+            .map_err(|e| anyhow!("Error: can't read keys from {}: {e}", self.dir))?;
+        return Ok(());
+    }
+}
@@ -0,0 +1,155 @@
+#[cfg(test)]
+mod ca_tests;
+
+use crate::{
+    builtin::sign_with,
+    keystore::Keystore,
+};
+
+use socket_stdinout::{
+    agent::flags::RSA_SHA2_512,
+    types::DynError,
+};
+use std::{
+    fs::File,
+    io::Read,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use ssh_key::{
+    Certificate,
+    PrivateKey,
+    PublicKey,
+    Signature,
+    certificate::{Builder, CertType},
+    public::KeyData,
+};
+use signature::Signer;
+use anyhow::anyhow;
+
+const RANDOM_PATH: &str = "/dev/urandom";
+const NONCE_LEN: usize = 32;
+/// certificates are valid from this many seconds before they're issued,
+/// for client domain clocks running behind the vault's
+const CLOCK_SKEW: u64 = 60;
+/// the extensions ssh-keygen puts in user certificates by default
+const EXTENSIONS: [&str; 5] = [
+    "permit-X11-forwarding",
+    "permit-agent-forwarding",
+    "permit-port-forwarding",
+    "permit-pty",
+    "permit-user-rc",
+];
+
+fn random<const N: usize>() -> DynError<[u8; N]> {
+    let mut buf = [0u8; N];
+    File::open(RANDOM_PATH)?.read_exact(&mut buf)?;
+    return Ok(buf);
+}
+
+/// A user certificate as the domain's cert-* rules allow it.
+pub struct CertRequest<'a> {
+    pub public_key: &'a PublicKey,
+    /// shows up in the ssh server's logs
+    pub key_id: String,
+    pub principals: &'a [String],
+    /// seconds from now
+    pub validity: u32,
+    /// critical options, name and data
+    pub options: &'a [(String, String)],
+}
+
+impl CertRequest<'_> {
+    fn builder(&self, now: SystemTime) -> DynError<Builder> {
+        let now = now.duration_since(UNIX_EPOCH)?.as_secs();
+        let mut builder = Builder::new(
+            random::<NONCE_LEN>()?,
+            self.public_key.key_data().clone(),
+            now.saturating_sub(CLOCK_SKEW),
+            now + u64::from(self.validity),
+        )?;
+
+        builder.serial(u64::from_be_bytes(random()?))?;
+        builder.cert_type(CertType::User)?;
+        builder.key_id(&self.key_id)?;
+        builder.comment(self.public_key.comment())?;
+        for principal in self.principals {
+            builder.valid_principal(principal)?;
+        }
+        for (name, data) in self.options {
+            builder.critical_option(name, data)?;
+        }
+        for extension in EXTENSIONS {
+            builder.extension(extension, "")?;
+        }
+        return Ok(builder);
+    }
+}
+
+/// Signs with the CA key the way the built-in agent signs, ssh-key's
+/// own RSA signing trips over its RSA key conversion.
+struct CaSigner<'a>(&'a PrivateKey);
+
+impl Signer<Signature> for CaSigner<'_> {
+    fn try_sign(&self, msg: &[u8]) -> Result<Signature, signature::Error> {
+        return sign_with(self.0, msg, RSA_SHA2_512).ok()
+            .flatten()
+            .ok_or_else(signature::Error::new);
+    }
+}
+
+impl From<&CaSigner<'_>> for KeyData {
+    fn from(signer: &CaSigner<'_>) -> Self {
+        return signer.0.public_key().key_data().clone();
+    }
+}
+
+/// The vault as a user certificate authority for the client domain. A
+/// passphrase protected CA key is unlocked in a keystore of its own,
+/// which relocks the same way the built-in agent's does.
+pub struct Ca {
+    path: String,
+    domain: String,
+    askpass: Option<String>,
+    idle: Duration,
+    /// started the first time the CA key turns out to be encrypted
+    keystore: Option<Keystore>,
+}
+
+impl Ca {
+    pub fn new(path: &str, domain: &str, askpass: Option<&str>, idle: Duration) -> Self {
+        return Self {
+            path: path.to_string(),
+            domain: domain.to_string(),
+            askpass: askpass.map(str::to_string),
+            idle,
+            keystore: None,
+        };
+    }
+
+    /// Signs the certificate, None if the CA key has a passphrase and
+    /// stays locked. The key is read for every certificate, so a new CA
+    /// key takes effect without restarting anything.
+    pub fn issue(
+        &mut self,
+        request: &CertRequest,
+        now: SystemTime,
+    ) -> DynError<Option<Certificate>> {
+        let ca_key = PrivateKey::read_openssh_file(Path::new(&self.path))
+            .map_err(|e| anyhow!("Error: can't read the CA key {}: {e}", self.path))?;
+        let builder = request.builder(now)?;
+        if !ca_key.is_encrypted() {
+            return Ok(Some(builder.sign(&CaSigner(&ca_key))?));
+        }
+
+        if self.keystore.is_none() {
+            self.keystore = Some(Keystore::new(&self.domain, self.askpass.as_deref(), self.idle)?);
+        }
+        let keystore = self.keystore.as_mut().expect("started above");
+        let blob = ca_key.public_key().to_bytes()?;
+        let cert = keystore.with_key(&blob, &[(&blob, &ca_key)], |private| {
+            return builder.sign(&CaSigner(private));
+        })?;
+        return Ok(cert.transpose()?);
+    }
+}
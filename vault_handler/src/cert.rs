@@ -0,0 +1,102 @@
+#[cfg(test)]
+mod cert_tests;
+
+use crate::payload::Payload;
+
+use socket_stdinout::{
+    agent::Reader,
+    types::DynError,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::anyhow;
+
+pub const CERT_SUFFIX: &str = "-cert-v01@openssh.com";
+const USER_CERT: u32 = 1;
+
+/// An OpenSSH certificate from a public key blob, the fields the vault
+/// checks and records. The signature isn't verified, that's the ssh
+/// server's job.
+pub struct Cert {
+    pub serial: u64,
+    /// a user certificate, host certificates can't log in
+    pub user: bool,
+    pub key_id: String,
+    /// no principals means every principal
+    pub principals: Vec<String>,
+    /// seconds since the epoch
+    pub valid_after: u64,
+    pub valid_before: u64,
+}
+
+impl Cert {
+    /// None for a blob of a plain key.
+    pub fn parse(blob: &[u8]) -> DynError<Option<Self>> {
+        let mut reader = Reader::new(blob);
+        let key_type = reader.str()?;
+        let Some(base_type) = key_type.strip_suffix(CERT_SUFFIX) else {
+            return Ok(None);
+        };
+
+        let _nonce = reader.string()?;
+        let public_fields = match base_type {
+            "ssh-ed25519" => 1,
+            "ssh-rsa" | "ecdsa-sha2-nistp256" | "ecdsa-sha2-nistp384"
+                | "ecdsa-sha2-nistp521" | "sk-ssh-ed25519@openssh.com" => 2,
+            "sk-ecdsa-sha2-nistp256@openssh.com" => 3,
+            "ssh-dss" => 4,
+            _ => return Err(anyhow!("Error: unsupported certificate type {key_type}").into()),
+        };
+        for _ in 0..public_fields {
+            reader.string()?;
+        }
+
+        let serial = reader.u64()?;
+        let user = reader.u32()? == USER_CERT;
+        let key_id = reader.str()?.to_string();
+
+        let mut principals = Vec::new();
+        let mut packed = Reader::new(reader.string()?);
+        while !packed.is_empty() {
+            principals.push(packed.str()?.to_string());
+        }
+
+        let valid_after = reader.u64()?;
+        let valid_before = reader.u64()?;
+        return Ok(Some(Self { serial, user, key_id, principals, valid_after, valid_before }));
+    }
+
+    pub fn is_valid_at(&self, now: SystemTime) -> bool {
+        let now = now.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        return self.valid_after <= now && now < self.valid_before;
+    }
+
+    /// Whether the certificate may sign the payload at now: it has to be
+    /// valid, and a userauth request has to log in as one of its
+    /// principals with a user certificate.
+    pub fn check(&self, payload: &Payload, now: SystemTime) -> Result<(), &'static str> {
+        if !self.is_valid_at(now) {
+            return Err("certificate is expired or not yet valid");
+        }
+
+        let Payload::Userauth(userauth) = payload else {
+            return Ok(());
+        };
+        if !self.user {
+            return Err("host certificates can't log in");
+        }
+        if !self.principals.is_empty() && !self.principals.contains(&userauth.user) {
+            return Err("certificate doesn't cover the user logged in as");
+        }
+        return Ok(());
+    }
+}
+
+/// false for a certificate that's expired, not yet valid or can't be
+/// read, true for everything else.
+pub fn current(blob: &[u8], now: SystemTime) -> bool {
+    match Cert::parse(blob) {
+        Ok(Some(cert)) => return cert.is_valid_at(now),
+        Ok(None) => return true,
+        Err(_) => return false,
+    }
+}
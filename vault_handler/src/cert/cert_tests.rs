@@ -0,0 +1,95 @@
+use super::{Cert, current};
+use crate::payload::{Payload, Userauth};
+use socket_stdinout::agent::{put_string, put_u32, put_u64};
+use std::time::{Duration, UNIX_EPOCH};
+
+fn cert_blob(cert_type: u32, principals: &[&str], valid_after: u64, valid_before: u64) -> Vec<u8> {
+    let mut blob = Vec::new();
+    put_string(&mut blob, "ssh-ed25519-cert-v01@openssh.com");
+    put_string(&mut blob, [7u8; 32]);
+    put_string(&mut blob, [1u8; 32]);
+    put_u64(&mut blob, 42);
+    put_u32(&mut blob, cert_type);
+    put_string(&mut blob, "work+personal");
+
+    let mut packed = Vec::new();
+    for principal in principals {
+        put_string(&mut packed, principal);
+    }
+    put_string(&mut blob, packed);
+
+    put_u64(&mut blob, valid_after);
+    put_u64(&mut blob, valid_before);
+    // critical options, extensions, reserved, CA key and signature
+    for _ in 0..5 {
+        put_string(&mut blob, b"");
+    }
+    return blob;
+}
+
+fn login(user: &str) -> Payload<'static> {
+    return Payload::Userauth(Userauth {
+        session_id: b"session id",
+        user: user.to_string(),
+        service: "ssh-connection".to_string(),
+        alg: "ssh-ed25519-cert-v01@openssh.com".to_string(),
+        host_key: None,
+    });
+}
+
+#[test]
+fn plain_keys_arent_certificates() {
+    let mut blob = Vec::new();
+    put_string(&mut blob, "ssh-ed25519");
+    put_string(&mut blob, [1u8; 32]);
+    assert!(Cert::parse(&blob).unwrap().is_none());
+    assert!(current(&blob, UNIX_EPOCH));
+}
+
+#[test]
+fn fields_are_parsed() {
+    let cert = Cert::parse(&cert_blob(1, &["alice", "deploy"], 100, 200)).unwrap().unwrap();
+    assert_eq!(cert.serial, 42);
+    assert!(cert.user);
+    assert_eq!(cert.key_id, "work+personal");
+    assert_eq!(cert.principals, ["alice", "deploy"]);
+    assert_eq!((cert.valid_after, cert.valid_before), (100, 200));
+}
+
+#[test]
+fn validity_window() {
+    let blob = cert_blob(1, &["alice"], 100, 200);
+    let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+    assert!(!current(&blob, at(99)));
+    assert!(current(&blob, at(100)));
+    assert!(current(&blob, at(199)));
+    assert!(!current(&blob, at(200)));
+
+    let cert = Cert::parse(&blob).unwrap().unwrap();
+    assert!(cert.check(&login("alice"), at(200)).is_err());
+    assert!(cert.check(&Payload::Unknown, at(50)).is_err());
+}
+
+#[test]
+fn principal_has_to_be_covered() {
+    let now = UNIX_EPOCH + Duration::from_secs(150);
+    let cert = Cert::parse(&cert_blob(1, &["alice"], 100, 200)).unwrap().unwrap();
+    assert!(cert.check(&login("alice"), now).is_ok());
+    assert!(cert.check(&login("root"), now).is_err());
+    // SSHSIG and friends don't log in as anyone
+    assert!(cert.check(&Payload::Sshsig { namespace: "git".to_string() }, now).is_ok());
+
+    let any = Cert::parse(&cert_blob(1, &[], 100, 200)).unwrap().unwrap();
+    assert!(any.check(&login("root"), now).is_ok());
+
+    let host = Cert::parse(&cert_blob(2, &[], 100, 200)).unwrap().unwrap();
+    assert!(host.check(&login("alice"), now).is_err());
+}
+
+#[test]
+fn malformed_certificates_are_hidden() {
+    let blob = cert_blob(1, &["alice"], 100, 200);
+    let truncated = &blob[..60];
+    assert!(Cert::parse(truncated).is_err());
+    assert!(!current(truncated, UNIX_EPOCH + Duration::from_secs(150)));
+}
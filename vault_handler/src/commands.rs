@@ -0,0 +1,178 @@
+use crate::{
+    agents,
+    kill,
+    relock,
+    config::Config,
+    audit::{Audit, Record, timestamp},
+    unlocks::Unlocks,
+    usage::{Usage, Counter},
+};
+
+use socket_stdinout::types::DynError;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::anyhow;
+
+const USAGE: &str = "usage: vault_handler verify-audit
+       vault_handler unlock <key> <domain> <minutes>
+       vault_handler lock <key> [<domain>]
+       vault_handler kill [<reason>]
+       vault_handler revive
+       vault_handler relock
+       vault_handler usage
+       vault_handler agents";
+
+/// Administration commands for a shell inside of the vault, main never
+/// reaches these when qrexec started the process.
+pub fn run(args: &[String]) -> DynError<()> {
+    match args.first().map(String::as_str) {
+        Some("verify-audit") => return verify_audit(),
+        Some("unlock") => return unlock(&args[1..]),
+        Some("lock") => return lock(&args[1..]),
+        Some("kill") => return kill(&args[1..].join(" ")),
+        Some("revive") => return revive(),
+        Some("relock") => return relock(),
+        Some("usage") => return usage(),
+        Some("agents") => return agents::supervise(&Config::load()?),
+        _ => return Err(anyhow!(USAGE).into()),
+    }
+}
+
+fn verify_audit() -> DynError<()> {
+    match Audit::new()?.verify() {
+        Ok(entries) => {
+            println!("audit log intact: {entries} entries");
+            return Ok(());
+        }
+
+        Err(problems) => {
+            for problem in &problems {
+                println!("{problem}");
+            }
+            return Err(anyhow!(
+                "Error: audit log failed verification, {} problems", problems.len()
+            ).into());
+        }
+    }
+}
+
+/// key is a SHA256: fingerprint or key comment, the way key blocks
+/// select keys, domain may contain * and ? wildcards.
+fn unlock(args: &[String]) -> DynError<()> {
+    let [key, domain, minutes] = args else {
+        return Err(anyhow!(USAGE).into());
+    };
+    let minutes: u64 = minutes.parse()
+        .map_err(|_| anyhow!("Error: {minutes} isn't a number of minutes"))?;
+
+    let secs = minutes.checked_mul(60)
+        .ok_or_else(|| anyhow!("Error: {minutes} minutes is too long"))?;
+    let until = SystemTime::now() + Duration::from_secs(secs);
+    Unlocks::new()?.unlock(key, domain, until)?;
+
+    let mut record = Record::new(domain, "unlock");
+    record.set("key", key.as_str());
+    record.set("until", timestamp(until));
+    Audit::new()?.write(record)?;
+
+    println!("{key} unlocked for {domain} until {}", timestamp(until));
+    return Ok(());
+}
+
+fn lock(args: &[String]) -> DynError<()> {
+    let (key, domain) = match args {
+        [key] => (key, None),
+        [key, domain] => (key, Some(domain.as_str())),
+        _ => return Err(anyhow!(USAGE).into()),
+    };
+
+    let closed = Unlocks::new()?.lock(key, domain)?;
+
+    let mut record = Record::new(domain.unwrap_or("*"), "lock");
+    record.set("key", key.as_str());
+    record.set("closed", closed as u64);
+    Audit::new()?.write(record)?;
+
+    println!("{key} locked, {closed} unlock windows closed");
+    return Ok(());
+}
+
+/// Engages the kill switch, closes every unlock window and relocks the
+/// keystore, none of them come back with a revive.
+fn kill(reason: &str) -> DynError<()> {
+    kill::engage(reason)?;
+    let closed = Unlocks::new()?.clear()?;
+    relock::request()?;
+
+    let mut record = Record::new("*", "kill");
+    record.set("reason", reason);
+    record.set("closed", closed as u64);
+    Audit::new()?.write(record)?;
+
+    println!("kill switch engaged, every request is refused until `vault_handler revive`");
+    return Ok(());
+}
+
+/// Drops the decrypted keys of every vault_handler's keystore, they're
+/// asked for again on next use.
+fn relock() -> DynError<()> {
+    relock::request()?;
+    Audit::new()?.write(Record::new("*", "relock"))?;
+    println!("keystore relock requested, running vault_handlers relock within a second");
+    return Ok(());
+}
+
+fn revive() -> DynError<()> {
+    if !kill::release()? {
+        println!("kill switch wasn't engaged");
+        return Ok(());
+    }
+
+    Audit::new()?.write(Record::new("*", "revive"))?;
+    println!("kill switch released");
+    return Ok(());
+}
+
+/// Prints the signature counters of every key, for all domains together
+/// and per domain, then those of every domain.
+fn usage() -> DynError<()> {
+    let counters = Usage::new()?.counters()?;
+    if counters.is_empty() {
+        println!("no signatures counted yet");
+        return Ok(());
+    }
+
+    let now = SystemTime::now();
+    let mut keys: Vec<&str> = counters.iter().map(|c| c.key.as_str()).collect();
+    keys.sort();
+    keys.dedup();
+    for key in keys {
+        let of_key: Vec<&Counter> = counters.iter().filter(|c| c.key == key).collect();
+        let comment = of_key.iter()
+            .max_by_key(|c| c.last)
+            .map(|c| c.comment.as_str())
+            .unwrap_or_default();
+        println!("key {key} {comment}");
+        println!("    {:<24} {}", "all domains", totals(&of_key, now));
+        for counter in of_key {
+            println!("    {:<24} {}", counter.domain, totals(&[counter], now));
+        }
+    }
+
+    let mut domains: Vec<&str> = counters.iter().map(|c| c.domain.as_str()).collect();
+    domains.sort();
+    domains.dedup();
+    for domain in domains {
+        let of_domain: Vec<&Counter> = counters.iter().filter(|c| c.domain == domain).collect();
+        println!("domain {domain:<21} {}", totals(&of_domain, now));
+    }
+    return Ok(());
+}
+
+fn totals(counters: &[&Counter], now: SystemTime) -> String {
+    let today: u64 = counters.iter().map(|c| c.today(now)).sum();
+    let total: u64 = counters.iter().map(|c| c.total).sum();
+    let last = counters.iter().map(|c| c.last).max().unwrap_or_default();
+    return format!(
+        "today {today}, total {total}, last used {}",
+        timestamp(UNIX_EPOCH + Duration::from_secs(last)));
+}
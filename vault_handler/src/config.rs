@@ -0,0 +1,782 @@
+#[cfg(test)]
+mod config_tests;
+
+use crate::{
+    agents,
+    anomaly,
+    known_hosts::wildcard_match,
+};
+
+use socket_stdinout::{
+    ERR_LOG_DIR_NAME,
+    types::DynError,
+};
+use std::{
+    fs,
+    env,
+    io::ErrorKind::NotFound,
+};
+use anyhow::anyhow;
+
+const CONFIG_FNAME: &str = "vault.conf";
+const DEFAULT_KNOWN_HOSTS: &str = "known_hosts";
+
+/// $XDG_CONFIG_HOME/dir_name, falling back to $HOME/.config/dir_name.
+pub fn get_xdg_config_dir(dir_name: impl std::fmt::Display) -> DynError<String> {
+    const XDG_VAR: &str = "XDG_CONFIG_HOME";
+    const DEFAULT_VAR: &str = "HOME";
+    const DEFAULT_POSTFIX: &str = /*$HOME*/".config";
+
+    if let Ok(xdg_dir) = env::var(XDG_VAR) {
+        return Ok(format!("{}/{}", xdg_dir, dir_name));
+    } else {
+        return Ok(format!(
+            "{}/{}/{}",
+            env::var(DEFAULT_VAR)?,
+            DEFAULT_POSTFIX,
+            dir_name,
+        ));
+    }
+}
+
+/// What to do with a request a rule applies to.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+    Allow,
+    Deny,
+    /// ask the user in the vault through the askpass program
+    Confirm,
+}
+
+impl Action {
+    fn parse(args: &[&str]) -> DynError<Self> {
+        match args {
+            ["allow"] => return Ok(Self::Allow),
+            ["deny"] => return Ok(Self::Deny),
+            ["confirm"] => return Ok(Self::Confirm),
+            _ => return Err(anyhow!("expected one of allow, deny or confirm").into()),
+        }
+    }
+}
+
+/// Signatures per UTC day, and what to do with those past the limit.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Quota {
+    pub limit: u64,
+    pub action: Action,
+}
+
+impl Quota {
+    fn parse(args: &[&str]) -> DynError<Self> {
+        let [limit, action] = args else {
+            return Err(anyhow!("expected a number of signatures and deny or confirm").into());
+        };
+        let limit = positive(&[limit])?.into();
+        match Action::parse(&[action])? {
+            Action::Allow => return Err(anyhow!("expected deny or confirm").into()),
+            action => return Ok(Self { limit, action }),
+        }
+    }
+}
+
+/// What to do when a SIGN_REQUEST for an RSA key asks for an ssh-rsa,
+/// i.e. SHA-1, signature.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RsaSha1 {
+    Allow,
+    Deny,
+    /// sign with rsa-sha2-512 instead
+    Upgrade,
+}
+
+/// Which keys a `key` block applies to.
+#[derive(Clone)]
+enum KeySelector {
+    Any,
+    Fingerprint(String),
+    Comment(String),
+}
+
+impl KeySelector {
+    fn new(arg: &str) -> Self {
+        if arg == "*" {
+            return Self::Any;
+        } else if arg.starts_with("SHA256:") {
+            return Self::Fingerprint(arg.to_string());
+        } else {
+            return Self::Comment(arg.to_string());
+        }
+    }
+
+    fn matches(&self, fingerprint: &str, comment: Option<&str>) -> bool {
+        match self {
+            Self::Any => return true,
+            Self::Fingerprint(fp) => return fp == fingerprint,
+            Self::Comment(c) => return Some(c.as_str()) == comment,
+        }
+    }
+}
+
+/// Settings of a `key` block, every field left as None falls through to
+/// the next matching block.
+#[derive(Default, Clone)]
+pub struct KeyRules {
+    /// host names resolved through the known_hosts files, or host key
+    /// fingerprints, the key may only sign for.
+    pub allow_hosts: Option<Vec<String>>,
+    /// requests arriving through `ssh -A` on a remote host
+    pub forwarding: Option<Action>,
+    /// payload class, see Payload::class, and what to do with it. Once
+    /// set, classes that aren't listed are denied.
+    pub sign: Option<Vec<(String, Action)>>,
+    /// deny when unset
+    pub rsa_sha1: Option<RsaSha1>,
+    /// comment shown to domains with `comments alias`
+    pub alias: Option<String>,
+    /// the key is hidden and refused unless unlocked for the domain
+    /// with `vault_handler unlock`
+    pub require_unlock: Option<bool>,
+    /// the key's signatures for every domain together
+    pub daily_quota: Option<Quota>,
+}
+
+impl KeyRules {
+    fn set(&mut self, directive: &str, args: &[&str]) -> DynError<()> {
+        match directive {
+            "allow-hosts" => {
+                let hosts = self.allow_hosts.get_or_insert_default();
+                hosts.extend(non_empty(args)?.iter().map(|s| s.to_string()));
+            }
+
+            "forwarding" => self.forwarding = Some(Action::parse(args)?),
+
+            "sign" => {
+                let [class, action @ ..] = args else {
+                    return Err(anyhow!("sign takes a payload class and an action").into());
+                };
+
+                let known = ["userauth", "sshsig", "unknown"].contains(class)
+                    || class.strip_prefix("sshsig:").is_some_and(|ns| !ns.is_empty());
+                if !known {
+                    return Err(anyhow!(
+                        "payload class {class} isn't one of userauth, sshsig, \
+                        sshsig:<namespace> or unknown").into());
+                }
+
+                self.sign.get_or_insert_default()
+                    .push((class.to_string(), Action::parse(action)?));
+            }
+
+            "rsa-sha1" => self.rsa_sha1 = Some(match args {
+                ["allow"] => RsaSha1::Allow,
+                ["deny"] => RsaSha1::Deny,
+                ["upgrade"] => RsaSha1::Upgrade,
+                _ => return Err(anyhow!("expected one of allow, deny or upgrade").into()),
+            }),
+
+            "alias" => self.alias = Some(non_empty(args)?.join(" ")),
+
+            "require-unlock" => self.require_unlock = Some(match args {
+                ["yes"] => true,
+                ["no"] => false,
+                _ => return Err(anyhow!("expected yes or no").into()),
+            }),
+
+            "daily-quota" => self.daily_quota = Some(Quota::parse(args)?),
+
+            _ => return Err(anyhow!("unknown key directive {directive}").into()),
+        }
+        return Ok(());
+    }
+
+    /// fills the unset fields from a later matching block.
+    fn merge(&mut self, other: &KeyRules) {
+        if self.allow_hosts.is_none() {
+            self.allow_hosts = other.allow_hosts.clone();
+        }
+        if self.forwarding.is_none() {
+            self.forwarding = other.forwarding;
+        }
+        if self.sign.is_none() {
+            self.sign = other.sign.clone();
+        }
+        if self.rsa_sha1.is_none() {
+            self.rsa_sha1 = other.rsa_sha1;
+        }
+        if self.alias.is_none() {
+            self.alias = other.alias.clone();
+        }
+        if self.require_unlock.is_none() {
+            self.require_unlock = other.require_unlock;
+        }
+        if self.daily_quota.is_none() {
+            self.daily_quota = other.daily_quota;
+        }
+    }
+}
+
+/// How key comments in IDENTITIES_ANSWER reach a client domain.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Comments {
+    Keep,
+    Strip,
+    /// the key's alias, stripped for keys without one
+    Alias,
+    /// vault-name:comment
+    Prefix,
+}
+
+/// Settings of a `domain` block, matched against the qrexec source
+/// domain the same way key blocks are.
+#[derive(Default, Clone)]
+pub struct DomainRules {
+    pub comments: Option<Comments>,
+    /// SSH_AGENTC_EXTENSION names, or patterns, passed on to the agent
+    pub extensions: Option<Vec<String>>,
+    /// true if the domain may add keys to the vault agent, after
+    /// confirmation in the vault
+    pub import: Option<bool>,
+    /// seconds an imported key stays in the agent at most
+    pub import_lifetime: Option<u32>,
+    pub import_min_rsa_bits: Option<u32>,
+    /// public key files of decoy keys listed to the domain, never signed
+    /// with
+    pub canaries: Option<Vec<String>>,
+    pub agent_socket: Option<String>,
+    /// principals the ca-key certifies the domain's keys for, the domain
+    /// gets no certificates when unset
+    pub cert_principals: Option<Vec<String>>,
+    /// seconds a certificate is valid for
+    pub cert_validity: Option<u32>,
+    /// critical options, name and data, of every certificate
+    pub cert_options: Option<Vec<(String, String)>>,
+    /// the domain's signatures with every key together
+    pub daily_quota: Option<Quota>,
+}
+
+impl DomainRules {
+    fn set(&mut self, directive: &str, args: &[&str], dir: &str) -> DynError<()> {
+        match directive {
+            "comments" => self.comments = Some(match args {
+                ["keep"] => Comments::Keep,
+                ["strip"] => Comments::Strip,
+                ["alias"] => Comments::Alias,
+                ["prefix"] => Comments::Prefix,
+                _ => return Err(anyhow!(
+                    "expected one of keep, strip, alias or prefix").into()),
+            }),
+
+            "extensions" => {
+                let exts = self.extensions.get_or_insert_default();
+                exts.extend(args.iter().map(|s| s.to_string()));
+            }
+
+            "import" => self.import = Some(match args {
+                ["confirm"] => true,
+                ["deny"] => false,
+                _ => return Err(anyhow!("expected confirm or deny").into()),
+            }),
+
+            "import-lifetime" => self.import_lifetime = Some(positive(args)?),
+
+            "import-min-rsa-bits" => self.import_min_rsa_bits = Some(positive(args)?),
+
+            "agent-socket" => self.agent_socket = Some(one_path(args, dir)?),
+
+            "canary" => {
+                let canaries = self.canaries.get_or_insert_default();
+                for path in non_empty(args)? {
+                    canaries.push(expand_path(path, dir)?);
+                }
+            }
+
+            "cert-principals" => {
+                let principals = self.cert_principals.get_or_insert_default();
+                principals.extend(non_empty(args)?.iter().map(|s| s.to_string()));
+            }
+
+            "cert-validity" => self.cert_validity = Some(positive(args)?),
+
+            "daily-quota" => self.daily_quota = Some(Quota::parse(args)?),
+
+            "cert-option" => {
+                let option = match args {
+                    ["force-command", command @ ..] if !command.is_empty() =>
+                        ("force-command", command.join(" ")),
+                    ["source-address", addresses] => ("source-address", addresses.to_string()),
+                    ["verify-required"] => ("verify-required", String::new()),
+                    _ => return Err(anyhow!(
+                        "expected force-command <command>, source-address <addresses> \
+                        or verify-required").into()),
+                };
+                let options = self.cert_options.get_or_insert_default();
+                if options.iter().any(|(name, _)| name == option.0) {
+                    return Err(anyhow!("cert-option {} is set twice", option.0).into());
+                }
+                options.push((option.0.to_string(), option.1));
+            }
+
+            _ => return Err(anyhow!("unknown domain directive {directive}").into()),
+        }
+        return Ok(());
+    }
+
+    fn merge(&mut self, other: &DomainRules) {
+        if self.comments.is_none() {
+            self.comments = other.comments;
+        }
+        if self.extensions.is_none() {
+            self.extensions = other.extensions.clone();
+        }
+        if self.import.is_none() {
+            self.import = other.import;
+        }
+        if self.import_lifetime.is_none() {
+            self.import_lifetime = other.import_lifetime;
+        }
+        if self.import_min_rsa_bits.is_none() {
+            self.import_min_rsa_bits = other.import_min_rsa_bits;
+        }
+        if self.canaries.is_none() {
+            self.canaries = other.canaries.clone();
+        }
+        if self.agent_socket.is_none() {
+            self.agent_socket = other.agent_socket.clone();
+        }
+        if self.cert_principals.is_none() {
+            self.cert_principals = other.cert_principals.clone();
+        }
+        if self.cert_validity.is_none() {
+            self.cert_validity = other.cert_validity;
+        }
+        if self.cert_options.is_none() {
+            self.cert_options = other.cert_options.clone();
+        }
+        if self.daily_quota.is_none() {
+            self.daily_quota = other.daily_quota;
+        }
+    }
+}
+
+/// Settings of an `argument` block, matched against the qrexec service
+/// argument, the `work` of `qubes.SplitSSHAgent+work`.
+#[derive(Default, Clone)]
+pub struct ArgumentRules {
+    pub agent_socket: Option<String>,
+    /// the key profile: only keys matching one of these, fingerprints,
+    /// comments or *, are listed and may sign
+    keys: Option<Vec<KeySelector>>,
+}
+
+impl ArgumentRules {
+    fn set(&mut self, directive: &str, args: &[&str], dir: &str) -> DynError<()> {
+        match directive {
+            "agent-socket" => self.agent_socket = Some(one_path(args, dir)?),
+
+            "keys" => {
+                let keys = self.keys.get_or_insert_default();
+                keys.extend(non_empty(args)?.iter().map(|arg| KeySelector::new(arg)));
+            }
+
+            _ => return Err(anyhow!("unknown argument directive {directive}").into()),
+        }
+        return Ok(());
+    }
+
+    fn merge(&mut self, other: &ArgumentRules) {
+        if self.agent_socket.is_none() {
+            self.agent_socket = other.agent_socket.clone();
+        }
+        if self.keys.is_none() {
+            self.keys = other.keys.clone();
+        }
+    }
+
+    /// true if the key is in the profile, or there's no profile.
+    pub fn key_allowed(&self, fingerprint: &str, comment: Option<&str>) -> bool {
+        return self.keys.as_ref().is_none_or(|keys| {
+            keys.iter().any(|selector| selector.matches(fingerprint, comment))
+        });
+    }
+}
+
+/// A `profile` block: an ssh-agent run by `vault_handler agents` with
+/// the private keys in keys_dir loaded.
+pub struct Profile {
+    pub name: String,
+    /// profiles/<name> in the config directory when unset
+    pub keys_dir: String,
+    /// seconds the loaded keys stay in the agent, forever when unset
+    pub lifetime: Option<u32>,
+}
+
+impl Profile {
+    fn new(name: &str, dir: &str) -> DynError<Self> {
+        let valid = name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+        if name.is_empty() || name.starts_with('.') || !valid {
+            return Err(anyhow!(
+                "profile names are letters, digits, -, _ and ., got {name}").into());
+        }
+        return Ok(Self {
+            name: name.to_string(),
+            keys_dir: format!("{dir}/profiles/{name}"),
+            lifetime: None,
+        });
+    }
+
+    fn set(&mut self, directive: &str, args: &[&str], dir: &str) -> DynError<()> {
+        match directive {
+            "keys-dir" => self.keys_dir = one_path(args, dir)?,
+
+            "lifetime" => self.lifetime = Some(positive(args)?),
+
+            _ => return Err(anyhow!("unknown profile directive {directive}").into()),
+        }
+        return Ok(());
+    }
+}
+
+enum Block {
+    Global,
+    Key,
+    Domain,
+    Argument,
+    Profile,
+}
+
+/// vault.conf from the split-ssh config directory, sshd_config style:
+///
+/// ```text
+/// known-hosts ~/.ssh/known_hosts
+/// askpass /usr/bin/ssh-askpass
+/// alert-command ~/bin/split-ssh-alert
+/// anomaly-alerts new-host new-domain burst odd-hour  # or none
+/// agent-wait 10
+/// agent-start yes
+/// builtin-keys ~/vault-keys  # with the builtin-agent feature
+/// keystore-idle 300
+/// ca-key ~/ca/user_ca
+///
+/// key SHA256:...           # or a key comment, or * for every key
+///     allow-hosts github.com
+///     forwarding confirm
+///     sign userauth allow
+///     sign sshsig:git confirm
+///     rsa-sha1 upgrade
+///     alias deploy-key
+///     require-unlock yes
+///     daily-quota 50 confirm
+///
+/// domain work-*            # qrexec source domain, * and ? wildcards
+///     comments alias
+///     extensions session-bind@openssh.com *@example.com
+///     import confirm
+///     import-lifetime 3600
+///     import-min-rsa-bits 3072
+///     canary canary.pub
+///     agent-socket /run/user/1000/work-agent.sock
+///     cert-principals alice deploy
+///     cert-validity 300
+///     cert-option force-command /usr/bin/deploy
+///     daily-quota 200 deny
+///
+/// argument personal        # qrexec service argument, * and ? wildcards
+///     agent-socket /run/user/1000/personal-agent.sock
+///     keys SHA256:... github-key
+///
+/// profile deploy           # an agent run by `vault_handler agents`
+///     keys-dir ~/deploy-keys
+///     lifetime 28800
+///
+/// domain ci
+///     agent-profile deploy # agent-socket of the profile's agent
+/// ```
+///
+/// Blocks are matched in file order and the first block to set a
+/// directive wins.
+#[derive(Default)]
+pub struct Config {
+    pub known_hosts: Vec<String>,
+    pub askpass: Option<String>,
+    /// run with the event, domain and a message when an alert is raised
+    pub alert_command: Option<String>,
+    /// the kinds of anomaly alerts are raised for, every kind when unset
+    pub anomaly_alerts: Option<Vec<&'static str>>,
+    /// name used by `comments prefix`, the host name when unset
+    pub vault_name: Option<String>,
+    /// the agent for domains and arguments without an agent-socket,
+    /// $SSH_AUTH_SOCK or another agent found in the usual places when
+    /// unset
+    pub agent_socket: Option<String>,
+    /// seconds to wait for the agent to appear
+    pub agent_wait: Option<u32>,
+    /// start an ssh-agent when there's none to wait for
+    pub agent_start: bool,
+    /// private key files signed with by vault_handler itself, for
+    /// domains and arguments without an agent-socket
+    #[cfg(feature = "builtin-agent")]
+    pub builtin_keys: Option<String>,
+    /// seconds the built-in agent's passphrase protected keys stay
+    /// decrypted without being used
+    #[cfg(feature = "builtin-agent")]
+    pub keystore_idle: Option<u32>,
+    /// the private key certificates for domains with cert-principals
+    /// are signed with
+    #[cfg(feature = "builtin-agent")]
+    pub ca_key: Option<String>,
+    keys: Vec<(KeySelector, KeyRules)>,
+    domains: Vec<(String, DomainRules)>,
+    arguments: Vec<(String, ArgumentRules)>,
+    pub profiles: Vec<Profile>,
+}
+
+impl Config {
+    /// A missing config file gives the defaults, which enforce nothing.
+    pub fn load() -> DynError<Self> {
+        let dir = get_xdg_config_dir(ERR_LOG_DIR_NAME)?;
+        let path = format!("{dir}/{CONFIG_FNAME}");
+
+        let mut config = match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text, &dir)
+                .map_err(|e| anyhow!("Error: {path}: {e}"))?,
+            Err(e) if e.kind() == NotFound => Self::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        if config.known_hosts.is_empty() {
+            config.known_hosts.push(format!("{dir}/{DEFAULT_KNOWN_HOSTS}"));
+        }
+        return Ok(config);
+    }
+
+    pub(crate) fn parse(text: &str, dir: &str) -> DynError<Self> {
+        let mut config = Self::default();
+        let mut block = Block::Global;
+        // profiles used by domain and argument blocks, with their lines
+        let mut used = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            // `#` starts a comment at the start of a line or after
+            // whitespace, inside a value it is part of the value
+            let words: Vec<&str> = line.split_whitespace()
+                .take_while(|word| !word.starts_with('#'))
+                .collect();
+            let Some((&directive, args)) = words.split_first() else {
+                continue;
+            };
+
+            let res = match (directive, &block) {
+                ("agent-profile", Block::Domain | Block::Argument) => match args {
+                    [name] => agents::socket(name).map(|socket| {
+                        used.push((i, name.to_string()));
+                        let agent_socket = match block {
+                            Block::Domain => config.domains.last_mut()
+                                .map(|(_, rules)| &mut rules.agent_socket),
+                            _ => config.arguments.last_mut()
+                                .map(|(_, rules)| &mut rules.agent_socket),
+                        };
+                        if let Some(agent_socket) = agent_socket {
+                            *agent_socket = Some(socket);
+                        }
+                    }),
+                    _ => Err(anyhow!("agent-profile takes one name").into()),
+                },
+
+                ("profile", _) => match args {
+                    [name] => Profile::new(name, dir).map(|profile| {
+                        config.profiles.push(profile);
+                        block = Block::Profile;
+                    }),
+                    _ => Err(anyhow!("profile takes one name").into()),
+                },
+
+                ("key", _) => match args {
+                    [selector] => {
+                        config.keys.push((KeySelector::new(selector), KeyRules::default()));
+                        block = Block::Key;
+                        Ok(())
+                    }
+                    _ => Err(anyhow!("key takes one selector").into()),
+                },
+
+                ("domain", _) => match args {
+                    [pattern] => {
+                        config.domains.push((pattern.to_string(), DomainRules::default()));
+                        block = Block::Domain;
+                        Ok(())
+                    }
+                    _ => Err(anyhow!("domain takes one pattern").into()),
+                },
+
+                ("argument", _) => match args {
+                    [pattern] => {
+                        config.arguments.push((pattern.to_string(), ArgumentRules::default()));
+                        block = Block::Argument;
+                        Ok(())
+                    }
+                    _ => Err(anyhow!("argument takes one pattern").into()),
+                },
+
+                (_, Block::Profile) => config.profiles.last_mut()
+                    .map(|profile| profile.set(directive, args, dir))
+                    .unwrap_or(Ok(())),
+
+                (_, Block::Argument) => config.arguments.last_mut()
+                    .map(|(_, rules)| rules.set(directive, args, dir))
+                    .unwrap_or(Ok(())),
+
+                (_, Block::Domain) => config.domains.last_mut()
+                    .map(|(_, rules)| rules.set(directive, args, dir))
+                    .unwrap_or(Ok(())),
+
+                (_, Block::Key) => config.keys.last_mut()
+                    .map(|(_, rules)| rules.set(directive, args))
+                    .unwrap_or(Ok(())),
+
+                (_, Block::Global) => config.set(directive, args, dir),
+            };
+
+            if let Err(e) = res {
+                return Err(anyhow!("line {}: {e}", i + 1).into());
+            }
+        }
+
+        for (i, name) in used {
+            if !config.profiles.iter().any(|profile| profile.name == name) {
+                return Err(anyhow!("line {}: no profile block for {name}", i + 1).into());
+            }
+        }
+        for (i, profile) in config.profiles.iter().enumerate() {
+            if config.profiles[..i].iter().any(|p| p.name == profile.name) {
+                return Err(anyhow!("profile {} is defined twice", profile.name).into());
+            }
+        }
+
+        return Ok(config);
+    }
+
+    fn set(&mut self, directive: &str, args: &[&str], dir: &str) -> DynError<()> {
+        match directive {
+            "known-hosts" => {
+                for path in non_empty(args)? {
+                    self.known_hosts.push(expand_path(path, dir)?);
+                }
+            }
+
+            "askpass" => self.askpass = Some(one_path(args, dir)?),
+
+            "alert-command" => self.alert_command = Some(one_path(args, dir)?),
+
+            "anomaly-alerts" => self.anomaly_alerts = Some(match non_empty(args)? {
+                ["none"] => Vec::new(),
+                kinds => kinds.iter()
+                    .map(|kind| anomaly::KINDS.iter().find(|k| *k == kind).copied()
+                        .ok_or_else(|| anyhow!(
+                            "{kind} isn't one of {} or none", anomaly::KINDS.join(", "))))
+                    .collect::<Result<_, _>>()?,
+            }),
+
+            "agent-socket" => self.agent_socket = Some(one_path(args, dir)?),
+
+            "agent-wait" => self.agent_wait = Some(match args {
+                [secs] => secs.parse()
+                    .map_err(|_| anyhow!("{secs} isn't a number of seconds"))?,
+                _ => return Err(anyhow!("expected one number").into()),
+            }),
+
+            "agent-start" => self.agent_start = match args {
+                ["yes"] => true,
+                ["no"] => false,
+                _ => return Err(anyhow!("expected yes or no").into()),
+            },
+
+            #[cfg(feature = "builtin-agent")]
+            "builtin-keys" => self.builtin_keys = Some(one_path(args, dir)?),
+
+            #[cfg(feature = "builtin-agent")]
+            "keystore-idle" => self.keystore_idle = Some(positive(args)?),
+
+            #[cfg(feature = "builtin-agent")]
+            "ca-key" => self.ca_key = Some(one_path(args, dir)?),
+
+            #[cfg(not(feature = "builtin-agent"))]
+            "builtin-keys" | "keystore-idle" | "ca-key" => return Err(anyhow!(
+                "{directive} needs vault_handler built with the builtin-agent feature").into()),
+
+            "vault-name" => self.vault_name = Some(non_empty(args)?.join(" ")),
+
+            _ => return Err(anyhow!("unknown directive {directive}").into()),
+        }
+        return Ok(());
+    }
+
+    /// The merged rules of every key block matching the key.
+    pub fn key_rules(&self, fingerprint: &str, comment: Option<&str>) -> KeyRules {
+        let mut rules = KeyRules::default();
+        for (selector, block) in &self.keys {
+            if selector.matches(fingerprint, comment) {
+                rules.merge(block);
+            }
+        }
+        return rules;
+    }
+
+    /// The merged rules of every argument block matching the qrexec
+    /// service argument.
+    pub fn argument_rules(&self, argument: &str) -> ArgumentRules {
+        let mut rules = ArgumentRules::default();
+        for (pattern, block) in &self.arguments {
+            if wildcard_match(pattern, argument) {
+                rules.merge(block);
+            }
+        }
+        return rules;
+    }
+
+    /// The merged rules of every domain block matching the domain.
+    pub fn domain_rules(&self, domain: &str) -> DomainRules {
+        let mut rules = DomainRules::default();
+        for (pattern, block) in &self.domains {
+            if wildcard_match(pattern, domain) {
+                rules.merge(block);
+            }
+        }
+        return rules;
+    }
+}
+
+fn non_empty<'a, 'b>(args: &'a [&'b str]) -> DynError<&'a [&'b str]> {
+    if args.is_empty() {
+        return Err(anyhow!("missing argument").into());
+    }
+    return Ok(args);
+}
+
+fn one_path(args: &[&str], dir: &str) -> DynError<String> {
+    let [path] = args else {
+        return Err(anyhow!("expected one path").into());
+    };
+    return expand_path(path, dir);
+}
+
+fn positive(args: &[&str]) -> DynError<u32> {
+    match args {
+        [num] => match num.parse::<u32>() {
+            Ok(num) if num > 0 => return Ok(num),
+            _ => return Err(anyhow!("{num} isn't a positive number").into()),
+        },
+        _ => return Err(anyhow!("expected one number").into()),
+    }
+}
+
+/// ~/ is the home directory, relative paths are relative to the
+/// config directory.
+fn expand_path(path: &str, dir: &str) -> DynError<String> {
+    if let Some(rest) = path.strip_prefix("~/") {
+        return Ok(format!("{}/{rest}", env::var("HOME")?));
+    } else if path.starts_with('/') {
+        return Ok(path.to_string());
+    } else {
+        return Ok(format!("{dir}/{path}"));
+    }
+}
@@ -0,0 +1,147 @@
+use super::{Config, Action, Comments, Quota, RsaSha1};
+
+fn parse(text: &str) -> Config {
+    return Config::parse(text, "/config").unwrap();
+}
+
+fn parse_err(text: &str) -> String {
+    let Err(e) = Config::parse(text, "/config") else {
+        panic!("{text:?} was accepted");
+    };
+    return e.to_string();
+}
+
+#[test]
+fn sign_rules_parse() {
+    let config = parse("\
+        key deploy\n\
+        sign userauth allow\n\
+        sign sshsig:git confirm\n\
+        sign unknown deny\n\
+    ");
+    let rules = config.key_rules("SHA256:x", Some("deploy"));
+    assert_eq!(rules.sign.unwrap(), [
+        ("userauth".to_string(), Action::Allow),
+        ("sshsig:git".to_string(), Action::Confirm),
+        ("unknown".to_string(), Action::Deny),
+    ]);
+    assert!(config.key_rules("SHA256:x", Some("other")).sign.is_none());
+
+    assert!(parse_err("key *\n sign sshsig: allow").starts_with("line 2:"));
+    assert!(parse_err("key *\n sign git allow").contains("payload class git"));
+    assert!(parse_err("key *\n sign userauth").contains("allow, deny or confirm"));
+}
+
+#[test]
+fn blocks_merge_in_file_order() {
+    let config = parse("\
+        # global settings\n\
+        known-hosts ~/.ssh/known_hosts extra_hosts\n\
+        askpass /usr/bin/ssh-askpass\n\
+        vault-name my vault\n\
+        \n\
+        key SHA256:abc\n\
+        allow-hosts github.com\n\
+        forwarding deny  # not through ssh -A\n\
+        key *\n\
+        allow-hosts example.org\n\
+        forwarding confirm\n\
+        rsa-sha1 upgrade\n\
+        daily-quota 50 confirm\n\
+        \n\
+        domain work-*\n\
+        comments strip\n\
+        import confirm\n\
+        domain *\n\
+        comments alias\n\
+        extensions session-bind@openssh.com *@example.com\n\
+        \n\
+        argument personal\n\
+        agent-socket personal.sock\n\
+        keys SHA256:abc github-key\n\
+    ");
+
+    let home = std::env::var("HOME").unwrap();
+    assert_eq!(config.known_hosts, [
+        format!("{home}/.ssh/known_hosts"), "/config/extra_hosts".to_string()]);
+    assert_eq!(config.askpass.as_deref(), Some("/usr/bin/ssh-askpass"));
+    assert_eq!(config.vault_name.as_deref(), Some("my vault"));
+
+    let rules = config.key_rules("SHA256:abc", None);
+    assert_eq!(rules.allow_hosts.unwrap(), ["github.com"]);
+    assert_eq!(rules.forwarding, Some(Action::Deny));
+    assert_eq!(rules.rsa_sha1, Some(RsaSha1::Upgrade));
+    assert_eq!(rules.daily_quota, Some(Quota { limit: 50, action: Action::Confirm }));
+    let rules = config.key_rules("SHA256:def", Some("github-key"));
+    assert_eq!(rules.allow_hosts.unwrap(), ["example.org"]);
+    assert_eq!(rules.forwarding, Some(Action::Confirm));
+
+    let work = config.domain_rules("work-mail");
+    assert_eq!(work.comments, Some(Comments::Strip));
+    assert_eq!(work.import, Some(true));
+    assert_eq!(work.extensions.unwrap(), ["session-bind@openssh.com", "*@example.com"]);
+    let other = config.domain_rules("personal");
+    assert_eq!(other.comments, Some(Comments::Alias));
+    assert_eq!(other.import, None);
+
+    let personal = config.argument_rules("personal");
+    assert_eq!(personal.agent_socket.as_deref(), Some("/config/personal.sock"));
+    assert!(personal.key_allowed("SHA256:abc", None));
+    assert!(personal.key_allowed("SHA256:def", Some("github-key")));
+    assert!(!personal.key_allowed("SHA256:def", Some("other")));
+    assert!(config.argument_rules("work").key_allowed("SHA256:def", None));
+}
+
+#[test]
+fn hashes_inside_values_are_kept() {
+    let config = parse("\
+        askpass /opt/ask#pass #the askpass\n\
+        key laptop#2\n\
+        #forwarding allow\n\
+        forwarding deny # not through ssh -A\n\
+    ");
+    assert_eq!(config.askpass.as_deref(), Some("/opt/ask#pass"));
+    assert_eq!(config.key_rules("SHA256:x", Some("laptop#2")).forwarding, Some(Action::Deny));
+    assert!(config.key_rules("SHA256:x", Some("laptop")).forwarding.is_none());
+}
+
+#[test]
+fn profiles_are_checked() {
+    let config = parse("\
+        profile deploy\n\
+        lifetime 28800\n\
+        domain ci\n\
+        agent-profile deploy\n\
+    ");
+    assert_eq!(config.profiles[0].keys_dir, "/config/profiles/deploy");
+    assert_eq!(config.profiles[0].lifetime, Some(28800));
+    let socket = config.domain_rules("ci").agent_socket.unwrap();
+    assert!(socket.ends_with("/deploy.sock"), "{socket}");
+
+    assert_eq!(parse_err("domain ci\nagent-profile deploy"), "line 2: no profile block for deploy");
+    assert_eq!(parse_err("profile a\nprofile a"), "profile a is defined twice");
+    assert!(parse_err("profile ../a").contains("profile names"));
+}
+
+#[test]
+fn mistakes_are_refused_with_their_line() {
+    assert_eq!(parse_err("askpass /bin/true\nno-such thing"), "line 2: unknown directive no-such");
+    assert_eq!(parse_err("key *\nimport confirm"), "line 2: unknown key directive import");
+    assert_eq!(parse_err("domain *\nsign userauth allow"),
+        "line 2: unknown domain directive sign");
+    assert_eq!(parse_err("key a b"), "line 1: key takes one selector");
+    assert_eq!(parse_err("domain *\nimport-lifetime 0"), "line 2: 0 isn't a positive number");
+    assert_eq!(parse_err("domain *\nforwarding maybe"),
+        "line 2: unknown domain directive forwarding");
+    assert_eq!(parse_err("key *\nforwarding maybe"),
+        "line 2: expected one of allow, deny or confirm");
+    assert!(parse_err("domain *\ncert-option verify-required\ncert-option verify-required")
+        .contains("set twice"));
+    assert!(parse_err("anomaly-alerts sometimes").contains("isn't one of"));
+}
+
+#[cfg(not(feature = "builtin-agent"))]
+#[test]
+fn builtin_directives_need_the_feature() {
+    assert!(parse_err("builtin-keys ~/keys").contains("builtin-agent feature"));
+}
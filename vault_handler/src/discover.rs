@@ -0,0 +1,104 @@
+use crate::{
+    state,
+    audit::timestamp,
+    config::Config,
+};
+
+use socket_stdinout::{
+    self as sock,
+    ERR_LOG_DIR_NAME,
+    debug::append,
+    types::DynError,
+};
+use std::{
+    fs,
+    thread,
+    io::ErrorKind::NotFound,
+    os::unix::net::UnixStream,
+    process::{Command, Stdio},
+    time::{Duration, Instant, SystemTime},
+};
+use anyhow::anyhow;
+
+/// where `agent-start yes` starts an ssh-agent without an agent-socket
+const STARTED_FNAME: &str = "agent.sock";
+const START_LOCK_FNAME: &str = "agent.lock";
+const DEFAULT_WAIT_SECS: u32 = 10;
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const DEBUG_FNAME: &str = "Discover";
+
+fn listening(path: &str) -> bool {
+    return UnixStream::connect(path).is_ok();
+}
+
+/// The socket of the agent the requests go to, waiting up to agent-wait
+/// seconds for it to accept connections. routed is the agent-socket of
+/// the domain or argument; without one the global agent-socket is used,
+/// or the first agent sock::find_agent finds. With `agent-start yes` an
+/// ssh-agent is started on the global socket once the wait is over.
+pub fn agent_socket(config: &Config, routed: Option<&str>) -> DynError<String> {
+    let wait = config.agent_wait.unwrap_or(DEFAULT_WAIT_SECS);
+    let deadline = Instant::now() + Duration::from_secs(wait.into());
+    let started = state::path(STARTED_FNAME)?;
+    let configured = routed.or(config.agent_socket.as_deref());
+    let mut may_start = config.agent_start && routed.is_none();
+
+    loop {
+        let found = match configured {
+            Some(path) => listening(path).then(|| path.to_string()),
+            None => sock::find_agent()
+                .or_else(|| listening(&started).then(|| started.clone())),
+        };
+        if let Some(path) = found {
+            return Ok(path);
+        }
+
+        if Instant::now() < deadline {
+            thread::sleep(POLL_INTERVAL);
+        } else if may_start {
+            may_start = false;
+            let path = configured.unwrap_or(&started);
+            let msg = match start(path) {
+                Ok(()) => format!("started ssh-agent on {path}"),
+                Err(e) => e.to_string(),
+            };
+            append(
+                &format!("{} {msg}\n", timestamp(SystemTime::now())),
+                DEBUG_FNAME,
+                ERR_LOG_DIR_NAME);
+        } else {
+            return Err(anyhow!(
+                "Error: no ssh-agent listening on {} after {wait}s",
+                configured.unwrap_or("$SSH_AUTH_SOCK or the usual sockets"),
+            ).into());
+        }
+    }
+}
+
+/// Starts a backgrounded ssh-agent on path, unless another vault_handler
+/// got there first.
+fn start(path: &str) -> DynError<()> {
+    let lock = fs::File::create(state::path(START_LOCK_FNAME)?)?;
+    lock.lock()?;
+
+    if listening(path) {
+        return Ok(());
+    }
+    match fs::remove_file(path) {
+        Ok(()) => (),
+        Err(e) if e.kind() == NotFound => (),
+        Err(e) => return Err(e.into()),
+    }
+
+    // ssh-agent forks once its socket is bound, so it's listening as
+    // soon as this returns
+    let status = Command::new("ssh-agent")
+        .args(["-a", path])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!("Error: ssh-agent -a {path} failed, {status}").into());
+    }
+    return Ok(());
+}
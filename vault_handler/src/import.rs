@@ -0,0 +1,220 @@
+#[cfg(test)]
+mod import_tests;
+
+use crate::cert::CERT_SUFFIX;
+
+use socket_stdinout::{
+    agent::{self, msg, Reader},
+    types::DynError,
+};
+use anyhow::anyhow;
+
+const CONSTRAIN_LIFETIME: u8 = 1;
+const CONSTRAIN_CONFIRM: u8 = 2;
+const CONSTRAIN_EXTENSION: u8 = 255;
+
+/// extension constraints ssh-add sends for the key types read_key knows
+const RESTRICT_DEST_EXT: &str = "restrict-destination-v00@openssh.com";
+const ASSOCIATED_CERTS_EXT: &str = "associated-certs-v00@openssh.com";
+
+/// A key a client domain asks the vault agent to hold, from an
+/// ADD_IDENTITY or ADD_ID_CONSTRAINED.
+pub struct NewKey<'a> {
+    pub key_type: String,
+    /// the public key blob the key is listed and signed with
+    pub blob: Vec<u8>,
+    /// modulus size for RSA and DSA, curve size otherwise
+    pub bits: u32,
+    pub comment: String,
+    /// requested lifetime in seconds
+    pub lifetime: Option<u32>,
+    /// the key type and private key fields as sent
+    key: &'a [u8],
+    /// extension constraints as sent, kept when rewriting
+    extensions: Vec<&'a [u8]>,
+}
+
+impl<'a> NewKey<'a> {
+    pub fn parse(msg: &'a [u8]) -> DynError<Self> {
+        let constrained = match msg.first() {
+            Some(&msg::ADD_IDENTITY) => false,
+            Some(&msg::ADD_ID_CONSTRAINED) => true,
+            _ => return Err(anyhow!("Error: not an add identity request").into()),
+        };
+
+        let body = &msg[1..];
+        let mut reader = Reader::new(body);
+        let (key_type, blob, bits) = read_key(&mut reader)?;
+        let key = &body[..reader.pos()];
+        let comment = String::from_utf8_lossy(reader.string()?).into_owned();
+
+        let mut lifetime = None;
+        let mut extensions = Vec::new();
+        while constrained && !reader.is_empty() {
+            let start = reader.pos();
+            match reader.u8()? {
+                CONSTRAIN_LIFETIME => lifetime = Some(reader.u32()?),
+                CONSTRAIN_CONFIRM => (),
+                CONSTRAIN_EXTENSION => {
+                    match reader.str()? {
+                        RESTRICT_DEST_EXT => {
+                            reader.string()?;
+                        }
+                        ASSOCIATED_CERTS_EXT => {
+                            reader.bool()?;
+                            reader.string()?;
+                        }
+                        name => return Err(anyhow!(
+                            "Error: unknown key constraint {name}").into()),
+                    }
+                    extensions.push(&body[start..reader.pos()]);
+                }
+                other => return Err(anyhow!(
+                    "Error: unknown key constraint {other}").into()),
+            }
+        }
+
+        if !reader.is_empty() {
+            return Err(anyhow!("Error: trailing data after add identity").into());
+        }
+
+        return Ok(Self { key_type, blob, bits, comment, lifetime, key, extensions });
+    }
+
+    /// Why the key is too weak to be let into the vault, if it is.
+    pub fn weakness(&self, min_rsa_bits: u32) -> Option<String> {
+        let base_type = self.key_type.trim_end_matches(CERT_SUFFIX);
+        if base_type == "ssh-dss" {
+            return Some("DSA keys are refused".to_string());
+        } else if base_type == "ssh-rsa" && self.bits < min_rsa_bits {
+            return Some(format!(
+                "{} bit RSA key is below the minimum of {min_rsa_bits}", self.bits));
+        } else {
+            return None;
+        }
+    }
+
+    /// the requested lifetime, capped at max seconds.
+    pub fn lifetime_within(&self, max: u32) -> u32 {
+        return self.lifetime.map_or(max, |asked| asked.min(max));
+    }
+
+    /// The key as an ADD_ID_CONSTRAINED that expires after lifetime
+    /// seconds and needs confirming in the vault on every use.
+    pub fn constrained(&self, lifetime: u32) -> Vec<u8> {
+        let mut request = vec![msg::ADD_ID_CONSTRAINED];
+        request.extend_from_slice(self.key);
+        agent::put_string(&mut request, self.comment.as_bytes());
+        for extension in &self.extensions {
+            request.extend_from_slice(extension);
+        }
+
+        agent::put_u8(&mut request, CONSTRAIN_LIFETIME);
+        agent::put_u32(&mut request, lifetime);
+        agent::put_u8(&mut request, CONSTRAIN_CONFIRM);
+        return request;
+    }
+}
+
+/// reads the key type and private key fields of an add identity request,
+/// returns the type, public key blob and key size.
+fn read_key(reader: &mut Reader) -> DynError<(String, Vec<u8>, u32)> {
+    let key_type = reader.str()?.to_string();
+    let mut blob = Vec::new();
+    agent::put_string(&mut blob, &key_type);
+
+    if key_type.ends_with(CERT_SUFFIX) {
+        let cert = reader.string()?;
+        let bits = cert_bits(&key_type, cert)?;
+        let private_fields = match key_type.trim_end_matches(CERT_SUFFIX) {
+            "ssh-rsa" => 4,
+            "ssh-dss" | "ecdsa-sha2-nistp256" | "ecdsa-sha2-nistp384"
+                | "ecdsa-sha2-nistp521" => 1,
+            "ssh-ed25519" => 2,
+            _ => return Err(anyhow!("Error: unsupported key type {key_type}").into()),
+        };
+        for _ in 0..private_fields {
+            reader.string()?;
+        }
+        return Ok((key_type, cert.to_vec(), bits));
+    }
+
+    let bits = match key_type.as_str() {
+        "ssh-rsa" => {
+            let (n, e) = (reader.string()?, reader.string()?);
+            for _ in 0..4 {
+                reader.string()?;
+            }
+            agent::put_string(&mut blob, e);
+            agent::put_string(&mut blob, n);
+            mpint_bits(n)
+        }
+
+        "ssh-dss" => {
+            let public: Vec<&[u8]> = (0..4)
+                .map(|_| reader.string())
+                .collect::<DynError<_>>()?;
+            reader.string()?;
+            for field in &public {
+                agent::put_string(&mut blob, field);
+            }
+            mpint_bits(public[0])
+        }
+
+        "ecdsa-sha2-nistp256" | "ecdsa-sha2-nistp384" | "ecdsa-sha2-nistp521" => {
+            let (curve, point) = (reader.str()?, reader.string()?);
+            reader.string()?;
+            agent::put_string(&mut blob, curve);
+            agent::put_string(&mut blob, point);
+            curve_bits(curve)?
+        }
+
+        "ssh-ed25519" => {
+            let public = reader.string()?;
+            reader.string()?;
+            agent::put_string(&mut blob, public);
+            256
+        }
+
+        _ => return Err(anyhow!("Error: unsupported key type {key_type}").into()),
+    };
+
+    return Ok((key_type, blob, bits));
+}
+
+/// key size from the public key fields at the start of a certificate.
+fn cert_bits(key_type: &str, cert: &[u8]) -> DynError<u32> {
+    let mut reader = Reader::new(cert);
+    if reader.str()? != key_type {
+        return Err(anyhow!("Error: certificate type doesn't match the key type").into());
+    }
+    let _nonce = reader.string()?;
+
+    match key_type.trim_end_matches(CERT_SUFFIX) {
+        "ssh-rsa" => {
+            let _e = reader.string()?;
+            return Ok(mpint_bits(reader.string()?));
+        }
+        "ssh-dss" => return Ok(mpint_bits(reader.string()?)),
+        "ssh-ed25519" => return Ok(256),
+        _ => return curve_bits(reader.str()?),
+    }
+}
+
+fn curve_bits(curve: &str) -> DynError<u32> {
+    match curve {
+        "nistp256" => return Ok(256),
+        "nistp384" => return Ok(384),
+        "nistp521" => return Ok(521),
+        _ => return Err(anyhow!("Error: unknown curve {curve}").into()),
+    }
+}
+
+/// significant bits of an mpint.
+fn mpint_bits(mpint: &[u8]) -> u32 {
+    let Some(start) = mpint.iter().position(|b| *b != 0) else {
+        return 0;
+    };
+    let len = (mpint.len() - start) as u32;
+    return len * 8 - mpint[start].leading_zeros();
+}
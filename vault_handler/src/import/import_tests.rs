@@ -0,0 +1,89 @@
+use super::NewKey;
+use socket_stdinout::agent::{msg, put_string, put_u8, put_u32, Reader};
+
+fn add_ed25519(msg_type: u8) -> Vec<u8> {
+    let mut request = vec![msg_type];
+    put_string(&mut request, "ssh-ed25519");
+    put_string(&mut request, [1u8; 32]);
+    put_string(&mut request, [2u8; 64]);
+    put_string(&mut request, "imported");
+    return request;
+}
+
+fn add_rsa(modulus_len: usize) -> Vec<u8> {
+    let mut n = vec![0u8];
+    n.extend(vec![0xffu8; modulus_len]);
+
+    let mut request = vec![msg::ADD_IDENTITY];
+    put_string(&mut request, "ssh-rsa");
+    put_string(&mut request, &n);
+    put_string(&mut request, [1u8, 0, 1]);
+    for _ in 0..4 {
+        put_string(&mut request, [3u8; 16]);
+    }
+    put_string(&mut request, "rsa");
+    return request;
+}
+
+#[test]
+fn add_identity_is_constrained() {
+    let request = add_ed25519(msg::ADD_IDENTITY);
+    let key = NewKey::parse(&request).unwrap();
+    assert_eq!(key.key_type, "ssh-ed25519");
+    assert_eq!(key.comment, "imported");
+
+    let mut blob = Vec::new();
+    put_string(&mut blob, "ssh-ed25519");
+    put_string(&mut blob, [1u8; 32]);
+    assert_eq!(key.blob, blob);
+
+    let constrained = key.constrained(key.lifetime_within(600));
+    assert_eq!(constrained[0], msg::ADD_ID_CONSTRAINED);
+    assert_eq!(constrained[1..(request.len())], request[1..]);
+
+    let mut reader = Reader::new(&constrained[request.len()..]);
+    assert_eq!(reader.u8().unwrap(), 1);
+    assert_eq!(reader.u32().unwrap(), 600);
+    assert_eq!(reader.u8().unwrap(), 2);
+    assert!(reader.is_empty());
+}
+
+#[test]
+fn requested_lifetime_is_capped() {
+    let mut request = add_ed25519(msg::ADD_ID_CONSTRAINED);
+    put_u8(&mut request, 1);
+    put_u32(&mut request, 60);
+    put_u8(&mut request, 2);
+
+    let key = NewKey::parse(&request).unwrap();
+    assert_eq!(key.lifetime_within(600), 60);
+
+    let mut forever = add_ed25519(msg::ADD_ID_CONSTRAINED);
+    put_u8(&mut forever, 1);
+    put_u32(&mut forever, u32::MAX);
+    assert_eq!(NewKey::parse(&forever).unwrap().lifetime_within(600), 600);
+}
+
+#[test]
+fn unknown_constraints_are_refused() {
+    let mut request = add_ed25519(msg::ADD_ID_CONSTRAINED);
+    put_u8(&mut request, 255);
+    put_string(&mut request, "something@example.com");
+    assert!(NewKey::parse(&request).is_err());
+
+    let mut request = add_ed25519(msg::ADD_ID_CONSTRAINED);
+    put_u8(&mut request, 3);
+    put_u32(&mut request, 10);
+    assert!(NewKey::parse(&request).is_err());
+}
+
+#[test]
+fn weak_rsa_keys_are_refused() {
+    let small = add_rsa(256);
+    let key = NewKey::parse(&small).unwrap();
+    assert_eq!(key.bits, 2048);
+    assert!(key.weakness(3072).is_some());
+
+    let big = add_rsa(384);
+    assert!(NewKey::parse(&big).unwrap().weakness(3072).is_none());
+}
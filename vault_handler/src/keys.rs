@@ -0,0 +1,59 @@
+use crate::encoding::{base64_unpadded, base64_decode};
+
+use socket_stdinout::{
+    agent::{
+        Reader,
+        Identity,
+        flags::{RSA_SHA2_256, RSA_SHA2_512},
+    },
+    types::DynError,
+};
+use std::fs;
+use sha2::{Sha256, Digest};
+use anyhow::anyhow;
+
+/// SHA256:... fingerprint of a public key blob, the same format
+/// `ssh-add -l` prints.
+pub fn fingerprint(blob: &[u8]) -> String {
+    return format!("SHA256:{}", base64_unpadded(&Sha256::digest(blob)));
+}
+
+/// The key type name at the start of a public key blob.
+pub fn key_type(blob: &[u8]) -> String {
+    return Reader::new(blob).str()
+        .map(str::to_string)
+        .unwrap_or_else(|_| "unknown".to_string());
+}
+
+/// The signature algorithm a SIGN_REQUEST with these flags produces.
+pub fn sig_alg(blob: &[u8], flags: u32) -> String {
+    let key_type = key_type(blob);
+    if key_type != "ssh-rsa" && key_type != "ssh-rsa-cert-v01@openssh.com" {
+        return key_type;
+    }
+
+    if flags & RSA_SHA2_512 != 0 {
+        return "rsa-sha2-512".to_string();
+    } else if flags & RSA_SHA2_256 != 0 {
+        return "rsa-sha2-256".to_string();
+    } else {
+        return "ssh-rsa".to_string();
+    }
+}
+
+/// The keys of an OpenSSH public key file, `<type> <base64> [comment]`
+/// per line.
+pub fn read_public_keys(path: &str) -> DynError<Vec<Identity>> {
+    let mut ids = Vec::new();
+    for line in fs::read_to_string(path)?.lines() {
+        let mut words = line.split_whitespace();
+        let (Some(_key_type), Some(key)) = (words.next(), words.next()) else {
+            continue;
+        };
+
+        let blob = base64_decode(key)
+            .ok_or_else(|| anyhow!("Error: {path}: malformed public key"))?;
+        ids.push(Identity { blob, comment: words.collect::<Vec<_>>().join(" ") });
+    }
+    return Ok(ids);
+}
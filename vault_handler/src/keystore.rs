@@ -0,0 +1,213 @@
+#[cfg(test)]
+mod keystore_tests;
+
+use crate::{
+    prompt,
+    relock,
+    audit::{Audit, Record, timestamp},
+};
+
+use socket_stdinout::{
+    ERR_LOG_DIR_NAME,
+    debug::append,
+    types::DynError,
+};
+use std::{
+    fs,
+    io,
+    thread,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant, SystemTime},
+};
+use ssh_key::PrivateKey;
+use anyhow::anyhow;
+
+const LOG_FNAME: &str = "Builtin";
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// the relock thread's stack is locked into memory with the rest
+const RELOCK_STACK_SIZE: usize = 64 * 1024;
+
+fn log(msg: &str) {
+    append(
+        &format!("{} {msg}\n", timestamp(SystemTime::now())),
+        LOG_FNAME,
+        ERR_LOG_DIR_NAME);
+}
+
+/// Keeps the process's memory, decrypted keys included, out of swap and
+/// core dumps. Both stay in effect after the keystore relocks.
+fn lock_memory() -> DynError<()> {
+    // SAFETY: neither call takes pointers
+    unsafe {
+        if libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) != 0 {
+            return Err(anyhow!(
+                "Error: can't lock memory for the keystore, {}; raise the memlock \
+                limit, e.g. LimitMEMLOCK= of the qrexec service",
+                io::Error::last_os_error()
+            ).into());
+        }
+        libc::prctl(libc::PR_SET_DUMPABLE, 0);
+    }
+    return Ok(());
+}
+
+struct Unlocked {
+    /// decrypted keys by public key blob, zeroized when dropped
+    keys: Vec<(Vec<u8>, PrivateKey)>,
+    unlocked_at: SystemTime,
+    last_use: Instant,
+}
+
+/// The decrypted copies of the built-in agent's passphrase protected
+/// keys. The user is asked for the passphrase when a locked key is first
+/// used; the keys are dropped again once they weren't used for the idle
+/// time, or when `vault_handler relock` asks every process to.
+pub struct Keystore {
+    unlocked: Arc<Mutex<Option<Unlocked>>>,
+    domain: String,
+    askpass: Option<String>,
+    audit: Audit,
+    memory_locked: bool,
+}
+
+impl Keystore {
+    pub fn new(domain: &str, askpass: Option<&str>, idle: Duration) -> DynError<Self> {
+        return Self::spawn(domain, askpass, idle, relock::path()?, Audit::new()?, false);
+    }
+
+    /// A keystore with the relock file and audit log in dir, which
+    /// leaves the test process's memory unlocked.
+    #[cfg(test)]
+    pub fn with_dir(
+        domain: &str,
+        askpass: Option<&str>,
+        idle: Duration,
+        dir: &str,
+    ) -> DynError<Self> {
+        let relock_path = format!("{dir}/relock");
+        return Self::spawn(domain, askpass, idle, relock_path, Audit::with_dir(dir), true);
+    }
+
+    fn spawn(
+        domain: &str,
+        askpass: Option<&str>,
+        idle: Duration,
+        relock_path: String,
+        audit: Audit,
+        memory_locked: bool,
+    ) -> DynError<Self> {
+        let unlocked: Arc<Mutex<Option<Unlocked>>> = Arc::default();
+
+        let (shared, relock_domain, relock_audit) =
+            (unlocked.clone(), domain.to_string(), audit.clone());
+        thread::Builder::new().stack_size(RELOCK_STACK_SIZE).spawn(move || loop {
+            thread::sleep(CHECK_INTERVAL);
+            let mut guard = shared.lock().unwrap_or_else(PoisonError::into_inner);
+            let Some(state) = guard.as_ref() else {
+                continue;
+            };
+
+            let requested = fs::metadata(&relock_path)
+                .and_then(|meta| meta.modified())
+                .is_ok_and(|modified| modified >= state.unlocked_at);
+            let reason = if requested {
+                "relock"
+            } else if state.last_use.elapsed() >= idle {
+                "idle"
+            } else {
+                continue;
+            };
+
+            *guard = None;
+            drop(guard);
+            let mut record = Record::new(&relock_domain, "keystore-relock");
+            record.set("reason", reason);
+            if let Err(e) = relock_audit.write(record) {
+                log(&e.to_string());
+            }
+        })?;
+
+        return Ok(Self {
+            unlocked,
+            domain: domain.to_string(),
+            askpass: askpass.map(str::to_string),
+            audit,
+            memory_locked,
+        });
+    }
+
+    /// Runs f with the decrypted key for blob. If it isn't unlocked yet the
+    /// user is asked for the passphrase, which unlocks every key in
+    /// encrypted it decrypts. None if they cancel or the key stays locked.
+    pub fn with_key<T>(
+        &mut self,
+        blob: &[u8],
+        encrypted: &[(&[u8], &PrivateKey)],
+        f: impl FnOnce(&PrivateKey) -> T,
+    ) -> DynError<Option<T>> {
+        let unlocked = self.unlocked.clone();
+        let mut guard = unlocked.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let is_unlocked = |guard: &Option<Unlocked>| guard.as_ref()
+            .is_some_and(|state| state.keys.iter().any(|(b, _)| b == blob));
+        if !is_unlocked(&guard) {
+            self.unlock(&mut guard, encrypted)?;
+        }
+        if !is_unlocked(&guard) {
+            return Ok(None);
+        }
+
+        let state = guard.as_mut().expect("unlocked above");
+        state.last_use = Instant::now();
+        let key = state.keys.iter()
+            .find(|(b, _)| b == blob)
+            .map(|(_, key)| key)
+            .expect("unlocked above");
+        return Ok(Some(f(key)));
+    }
+
+    fn unlock(
+        &mut self,
+        guard: &mut Option<Unlocked>,
+        encrypted: &[(&[u8], &PrivateKey)],
+    ) -> DynError<()> {
+        if !self.memory_locked {
+            lock_memory()?;
+            self.memory_locked = true;
+        }
+
+        let locked: Vec<_> = encrypted.iter()
+            .filter(|(blob, _)| !guard.as_ref()
+                .is_some_and(|state| state.keys.iter().any(|(b, _)| b == blob)))
+            .collect();
+
+        let msg = format!(
+            "Passphrase to unlock {} keys of the vault keystore for {}",
+            locked.len(), self.domain);
+        let Some(passphrase) = prompt::passphrase(self.askpass.as_deref(), &msg) else {
+            return Ok(());
+        };
+
+        let mut keys = Vec::new();
+        for (blob, key) in locked {
+            if let Ok(key) = key.decrypt(&*passphrase) {
+                keys.push((blob.to_vec(), key));
+            }
+        }
+
+        let mut record = Record::new(&self.domain, "keystore-unlock");
+        record.set("keys", keys.len() as u64);
+        self.audit.write(record)?;
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        let state = guard.get_or_insert_with(|| Unlocked {
+            keys: Vec::new(),
+            unlocked_at: SystemTime::now(),
+            last_use: Instant::now(),
+        });
+        state.keys.extend(keys);
+        return Ok(());
+    }
+}
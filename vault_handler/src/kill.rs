@@ -0,0 +1,59 @@
+use crate::{
+    state,
+    audit::timestamp,
+};
+
+use socket_stdinout::{
+    filter::AgentBackend,
+    types::DynError,
+};
+use std::{
+    fs,
+    io::ErrorKind::NotFound,
+    time::SystemTime,
+};
+use anyhow::anyhow;
+
+const KILL_FNAME: &str = "killed";
+
+/// The kill switch file in the state directory. While it exists every
+/// vault_handler answers SSH_AGENT_FAILURE to everything; `vault_handler
+/// kill` creates it, but touching it by hand works just as well.
+pub fn path() -> DynError<String> {
+    return state::path(KILL_FNAME);
+}
+
+/// true if the switch is engaged, or if the file can't be checked.
+pub fn engaged(path: &str) -> bool {
+    return fs::exists(path).unwrap_or(true);
+}
+
+pub fn engage(reason: &str) -> DynError<()> {
+    let text = format!("{} {reason}\n", timestamp(SystemTime::now()));
+    fs::write(path()?, text)?;
+    return Ok(());
+}
+
+/// false if the switch wasn't engaged.
+pub fn release() -> DynError<bool> {
+    match fs::remove_file(path()?) {
+        Ok(()) => return Ok(true),
+        Err(e) if e.kind() == NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    }
+}
+
+/// Stands in for the agent of a vault opened with the switch engaged.
+/// Requests are refused before they get here, one that does was sent
+/// after the switch was released and ends the connection.
+pub struct NoAgent;
+
+impl AgentBackend for NoAgent {
+    fn call(&mut self, _msg: &[u8]) -> DynError<Vec<u8>> {
+        return Err(anyhow!("Error: no agent, the kill switch was engaged at connect").into());
+    }
+
+    fn reconnect(&mut self) -> DynError<()> {
+        return Ok(());
+    }
+}
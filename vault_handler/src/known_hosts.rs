@@ -0,0 +1,179 @@
+#[cfg(test)]
+mod known_hosts_tests;
+
+use crate::{
+    keys,
+    encoding::base64_decode,
+};
+
+use std::fs;
+use hmac::{Hmac, KeyInit, Mac};
+use sha1::Sha1;
+
+const HASHED_PREFIX: &str = "|1|";
+
+#[derive(PartialEq)]
+enum Marker {
+    None,
+    CertAuthority,
+    Revoked,
+}
+
+enum Hosts {
+    Patterns(Vec<String>),
+    /// |1|salt|hash, HMAC-SHA1 of the host name keyed with the salt
+    Hashed { salt: Vec<u8>, hash: Vec<u8> },
+}
+
+struct Entry {
+    marker: Marker,
+    hosts: Hosts,
+    key: Vec<u8>,
+}
+
+impl Entry {
+    fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_whitespace();
+        let mut first = words.next()?;
+
+        let marker = match first {
+            "@cert-authority" => Marker::CertAuthority,
+            "@revoked" => Marker::Revoked,
+            _ => Marker::None,
+        };
+        if marker != Marker::None {
+            first = words.next()?;
+        }
+
+        let hosts = match first.strip_prefix(HASHED_PREFIX) {
+            Some(hashed) => {
+                let (salt, hash) = hashed.split_once('|')?;
+                Hosts::Hashed { salt: base64_decode(salt)?, hash: base64_decode(hash)? }
+            }
+            None => Hosts::Patterns(first.split(',').map(str::to_string).collect()),
+        };
+
+        let _key_type = words.next()?;
+        let key = base64_decode(words.next()?)?;
+        return Some(Self { marker, hosts, key });
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        match &self.hosts {
+            Hosts::Hashed { salt, hash } => {
+                let Ok(mut mac) = Hmac::<Sha1>::new_from_slice(salt) else {
+                    return false;
+                };
+                mac.update(host.as_bytes());
+                return mac.verify_slice(hash).is_ok();
+            }
+
+            Hosts::Patterns(patterns) => {
+                let mut matched = false;
+                for pattern in patterns {
+                    match pattern.strip_prefix('!') {
+                        Some(negated) if wildcard_match(negated, host) => return false,
+                        Some(_) => (),
+                        None => matched |= wildcard_match(pattern, host),
+                    }
+                }
+                return matched;
+            }
+        }
+    }
+}
+
+/// ssh pattern matching, `*` is any run of characters and `?` any one.
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let (pattern, text): (Vec<char>, Vec<char>) = (
+        pattern.to_lowercase().chars().collect(),
+        text.to_lowercase().chars().collect(),
+    );
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((bp, bt)) = backtrack {
+            p = bp + 1;
+            t = bt + 1;
+            backtrack = Some((bp, bt + 1));
+        } else {
+            return false;
+        }
+    }
+
+    return pattern[p..].iter().all(|c| *c == '*');
+}
+
+/// Host keys from known_hosts format files, used to turn host names in
+/// the config into the host keys a session-bind can carry.
+#[derive(Default)]
+pub struct KnownHosts {
+    entries: Vec<Entry>,
+}
+
+impl KnownHosts {
+    /// unreadable files and lines that don't parse are skipped.
+    pub fn load(paths: &[String]) -> Self {
+        let mut known_hosts = Self::default();
+        for path in paths {
+            if let Ok(text) = fs::read_to_string(path) {
+                known_hosts.add(&text);
+            }
+        }
+        return known_hosts;
+    }
+
+    pub fn add(&mut self, text: &str) {
+        self.entries.extend(text.lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .filter_map(Entry::parse));
+    }
+
+    fn is_revoked(&self, key: &[u8]) -> bool {
+        return self.entries.iter()
+            .any(|e| e.marker == Marker::Revoked && e.key == key);
+    }
+
+    /// true if host_key belongs to one of hosts, a host is either a name
+    /// looked up in the files or a SHA256: host key fingerprint.
+    pub fn host_allowed(&self, hosts: &[String], host_key: &[u8]) -> bool {
+        if self.is_revoked(host_key) {
+            return false;
+        }
+
+        let fingerprint = keys::fingerprint(host_key);
+        return hosts.iter().any(|host| {
+            if host.starts_with("SHA256:") {
+                return *host == fingerprint;
+            }
+
+            return self.entries.iter().any(|e| {
+                e.marker == Marker::None && e.key == host_key && e.matches(host)
+            });
+        });
+    }
+
+    /// the plain host names listed for a host key, for the audit log.
+    pub fn names_for(&self, host_key: &[u8]) -> Vec<String> {
+        let mut names = Vec::new();
+        for entry in &self.entries {
+            let Hosts::Patterns(ref patterns) = entry.hosts else {
+                continue;
+            };
+
+            if entry.marker == Marker::None && entry.key == host_key {
+                names.extend(patterns.iter()
+                    .filter(|p| !p.starts_with('!') && !p.contains(['*', '?']))
+                    .cloned());
+            }
+        }
+        return names;
+    }
+}
@@ -0,0 +1,50 @@
+use super::{KnownHosts, wildcard_match};
+use crate::{
+    keys::fingerprint,
+    encoding::base64_decode,
+};
+
+const KEY_A: &str = "AAAAC3NzaC1lZDI1NTE5AAAAAUE=";
+const KEY_B: &str = "AAAAC3NzaC1lZDI1NTE5AAAAAUI=";
+
+fn known_hosts() -> KnownHosts {
+    let mut known_hosts = KnownHosts::default();
+    known_hosts.add(&format!("\
+        # comment\n\
+        github.com,140.82.121.3 ssh-ed25519 {KEY_A}\n\
+        *.example.org,!bad.example.org ssh-ed25519 {KEY_B}\n\
+        |1|MDEyMzQ1Njc4OWFiY2RlZmdoaWo=|A0RKd05qVGgaWtj0PRBGfKKlq/4= ssh-ed25519 {KEY_B}\n\
+    "));
+    return known_hosts;
+}
+
+#[test]
+fn wildcards() {
+    assert!(wildcard_match("*.example.org", "git.example.org"));
+    assert!(wildcard_match("h?st*", "HOST-1"));
+    assert!(!wildcard_match("*.example.org", "example.org"));
+}
+
+#[test]
+fn host_names_resolve_to_keys() {
+    let known_hosts = known_hosts();
+    let (key_a, key_b) = (base64_decode(KEY_A).unwrap(), base64_decode(KEY_B).unwrap());
+    let github = ["github.com".to_string()];
+
+    assert!(known_hosts.host_allowed(&github, &key_a));
+    assert!(!known_hosts.host_allowed(&github, &key_b));
+    assert!(known_hosts.host_allowed(&["git.example.org".to_string()], &key_b));
+    assert!(!known_hosts.host_allowed(&["bad.example.org".to_string()], &key_b));
+    assert!(known_hosts.host_allowed(&["gitlab.com".to_string()], &key_b),
+        "hashed host name didn't match");
+    assert!(known_hosts.host_allowed(&[fingerprint(&key_a)], &key_a));
+    assert_eq!(known_hosts.names_for(&key_a), ["github.com", "140.82.121.3"]);
+}
+
+#[test]
+fn revoked_keys_are_refused() {
+    let mut known_hosts = known_hosts();
+    known_hosts.add(&format!("@revoked * ssh-ed25519 {KEY_A}"));
+    let key_a = base64_decode(KEY_A).unwrap();
+    assert!(!known_hosts.host_allowed(&["github.com".to_string()], &key_a));
+}
@@ -1,4 +1,38 @@
-use std::io;
+mod agents;
+mod alert;
+mod anomaly;
+mod audit;
+#[cfg(feature = "builtin-agent")]
+mod builtin;
+#[cfg(feature = "builtin-agent")]
+mod ca;
+mod cert;
+mod commands;
+mod config;
+mod discover;
+mod encoding;
+mod import;
+mod keys;
+mod kill;
+#[cfg(feature = "builtin-agent")]
+mod keystore;
+mod known_hosts;
+mod owners;
+mod payload;
+mod policy;
+mod prompt;
+mod relock;
+mod session;
+mod state;
+#[cfg(test)]
+mod testing;
+mod unlocks;
+mod usage;
+mod vault;
+
+use crate::vault::{Vault, DOMAIN_VAR};
+
+use std::{io, env};
 
 use socket_stdinout::{
     self as sock,
@@ -10,9 +44,53 @@ use socket_stdinout::{
 const DEBUG_FNAME: &str = "Main";
 
 fn main() -> DynError<()> {
+    // qrexec hands the service argument to us as well, so commands are
+    // only honoured outside of a qrexec call.
+    let args: Vec<String> = env::args().skip(1).collect();
+    if env::var_os(DOMAIN_VAR).is_none() && !args.is_empty() {
+        return commands::run(&args);
+    }
+
     let (stdin, stdout) = (io::stdin(), io::stdout());
 
-    let listener = match sock::SockStream::new() {
+    let vault = match Vault::new() {
+        Ok(vault) => vault,
+        Err(e) => {
+            append(
+                &e.to_string(),
+                DEBUG_FNAME,
+                ERR_LOG_DIR_NAME);
+            return Err(e);
+        }
+    };
+
+    if vault.killed() {
+        let res = sock::handle_backend(stdout, stdin, vault, Box::new(kill::NoAgent));
+        if let Err(e) = res {
+            append(
+                &e.to_string(),
+                DEBUG_FNAME,
+                ERR_LOG_DIR_NAME);
+            return Err(e);
+        }
+        return Ok(());
+    }
+
+    #[cfg(feature = "builtin-agent")]
+    if let Some(res) = vault.builtin().transpose() {
+        let res = res
+            .and_then(|backend| sock::handle_backend(stdout, stdin, vault, Box::new(backend)));
+        if let Err(e) = res {
+            append(
+                &e.to_string(),
+                DEBUG_FNAME,
+                ERR_LOG_DIR_NAME);
+            return Err(e);
+        }
+        return Ok(());
+    }
+
+    let listener = match sock::SockStream::connect(vault.agent_socket()) {
         Ok(listener) => listener,
         Err(e) => {
             append(
@@ -23,12 +101,12 @@ fn main() -> DynError<()> {
         }
     };
 
-    if let Err(e) = listener.handle_connections(stdout, stdin) {
+    if let Err(e) = listener.handle_filtered(stdout, stdin, vault) {
         append(
             &e.to_string(),
             DEBUG_FNAME,
             ERR_LOG_DIR_NAME);
-        return Err(e.into());
+        return Err(e);
     }
 
     return Ok(());
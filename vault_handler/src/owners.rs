@@ -0,0 +1,75 @@
+#[cfg(test)]
+mod owners_tests;
+
+use crate::{
+    state,
+    encoding::{base64_unpadded, base64_decode},
+};
+
+use socket_stdinout::types::DynError;
+
+const OWNERS_FNAME: &str = "owners";
+
+/// A key a client domain imported into the vault agent.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Owned {
+    pub domain: String,
+    pub blob: Vec<u8>,
+    /// socket of the agent holding the key
+    pub agent: String,
+}
+
+/// Which domain added which key to the shared vault agent, kept in the
+/// split-ssh state directory as `<domain> <base64 key blob> <agent socket>`
+/// lines.
+/// Keys in the agent without an entry were loaded in the vault itself.
+pub struct Owners {
+    path: String,
+}
+
+impl Owners {
+    pub fn new() -> DynError<Self> {
+        return Ok(Self { path: state::path(OWNERS_FNAME)? });
+    }
+
+    #[cfg(test)]
+    pub fn with_path(path: &str) -> Self {
+        return Self { path: path.to_string() };
+    }
+
+    /// Runs f on the entries and writes them back, with the file locked
+    /// against the vault_handlers of other domains for the duration, so
+    /// f can change the agent and the entries together.
+    pub fn update<T>(
+        &self,
+        f: impl FnOnce(&mut Vec<Owned>) -> DynError<T>,
+    ) -> DynError<T> {
+        return state::update(&self.path, |text| {
+            let mut owned = parse(text);
+            let res = f(&mut owned);
+            *text = owned.iter()
+                .map(|o| format!("{} {} {}\n", o.domain, base64_unpadded(&o.blob), o.agent))
+                .collect();
+            return res;
+        });
+    }
+}
+
+/// lines that don't parse are dropped.
+fn parse(text: &str) -> Vec<Owned> {
+    return text.lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ' ');
+            let (domain, blob) = (fields.next()?, base64_decode(fields.next()?)?);
+            let agent = fields.next()?.to_string();
+            Some(Owned { domain: domain.to_string(), blob, agent })
+        })
+        .collect();
+}
+
+/// The domain that imported the key, None for keys of the vault.
+pub fn owner<'a>(owned: &'a [Owned], blob: &[u8]) -> Option<&'a str> {
+    return owned.iter()
+        .find(|o| o.blob == blob)
+        .map(|o| o.domain.as_str());
+}
@@ -0,0 +1,35 @@
+use super::{Owners, Owned, owner};
+use crate::testing::TempDir;
+
+fn owners_at(name: &str) -> (Owners, TempDir) {
+    let dir = TempDir::new(&format!("owners-{name}"));
+    return (Owners::with_path(&dir.join("owners")), dir);
+}
+
+fn entry(domain: &str, blob: &[u8], agent: &str) -> Owned {
+    return Owned { domain: domain.to_string(), blob: blob.to_vec(), agent: agent.to_string() };
+}
+
+#[test]
+fn entries_persist_between_updates() {
+    let (owners, _dir) = owners_at("persist");
+    owners.update(|owned| {
+        owned.push(entry("work", b"work key", "/run/agent.sock"));
+        owned.push(entry("dev", &[0, 1, 2, 255], "/run/dev agent"));
+        Ok(())
+    }).unwrap();
+
+    let owned = owners.update(|owned| Ok(owned.clone())).unwrap();
+    assert_eq!(owner(&owned, b"work key"), Some("work"));
+    assert_eq!(owner(&owned, &[0, 1, 2, 255]), Some("dev"));
+    assert_eq!(owner(&owned, b"vault key"), None);
+    assert_eq!(owned[1].agent, "/run/dev agent");
+
+    owners.update(|owned| {
+        owned.retain(|o| o.domain != "work");
+        Ok(())
+    }).unwrap();
+    let owned = owners.update(|owned| Ok(owned.clone())).unwrap();
+    assert_eq!(owned.len(), 1);
+    assert_eq!(owner(&owned, b"work key"), None);
+}
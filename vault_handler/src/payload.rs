@@ -0,0 +1,107 @@
+#[cfg(test)]
+mod payload_tests;
+
+use socket_stdinout::{
+    agent::Reader,
+    types::DynError,
+};
+use anyhow::anyhow;
+
+const SSH_MSG_USERAUTH_REQUEST: u8 = 50;
+const SSHSIG_MAGIC: &[u8] = b"SSHSIG";
+const PUBLICKEY_METHOD: &str = "publickey";
+const HOSTBOUND_METHOD: &str = "publickey-hostbound-v00@openssh.com";
+
+/// RFC 4252 publickey authentication request, the data ssh signs to log
+/// into a server.
+pub struct Userauth<'a> {
+    pub session_id: &'a [u8],
+    /// the remote user name
+    pub user: String,
+    /// ssh-connection for a normal login
+    pub service: String,
+    /// public key algorithm the signature is made with
+    pub alg: String,
+    /// publickey-hostbound-v00@openssh.com carries the server host key
+    pub host_key: Option<&'a [u8]>,
+}
+
+/// What the data of a SIGN_REQUEST is.
+pub enum Payload<'a> {
+    Userauth(Userauth<'a>),
+    /// `ssh-keygen -Y sign`, git commit signing and friends
+    Sshsig { namespace: String },
+    Unknown,
+}
+
+impl<'a> Payload<'a> {
+    /// anything that doesn't parse exactly as one of the known formats,
+    /// trailing bytes included, is Unknown.
+    pub fn classify(data: &'a [u8]) -> Self {
+        if let Ok(userauth) = parse_userauth(data) {
+            return Self::Userauth(userauth);
+        } else if let Ok(namespace) = parse_sshsig(data) {
+            return Self::Sshsig { namespace };
+        } else {
+            return Self::Unknown;
+        }
+    }
+
+    /// the name sign rules match against, `sshsig:<namespace>` for SSHSIG.
+    pub fn class(&self) -> String {
+        match self {
+            Self::Userauth(_) => return "userauth".to_string(),
+            Self::Sshsig { namespace } => return format!("sshsig:{namespace}"),
+            Self::Unknown => return "unknown".to_string(),
+        }
+    }
+}
+
+fn parse_userauth(data: &[u8]) -> DynError<Userauth<'_>> {
+    let mut reader = Reader::new(data);
+    let session_id = reader.string()?;
+    if reader.u8()? != SSH_MSG_USERAUTH_REQUEST {
+        return Err(anyhow!("Error: not a userauth request").into());
+    }
+
+    let user = reader.str()?.to_string();
+    let service = reader.str()?.to_string();
+    let method = reader.str()?;
+    if !reader.bool()? {
+        return Err(anyhow!("Error: userauth request without a signature").into());
+    }
+
+    let alg = reader.str()?.to_string();
+    let _key = reader.string()?;
+
+    let host_key = match method {
+        PUBLICKEY_METHOD => None,
+        HOSTBOUND_METHOD => Some(reader.string()?),
+        _ => return Err(anyhow!("Error: unknown userauth method").into()),
+    };
+
+    if !reader.is_empty() {
+        return Err(anyhow!("Error: trailing data after userauth request").into());
+    }
+
+    return Ok(Userauth { session_id, user, service, alg, host_key });
+}
+
+/// returns the namespace of an SSHSIG signed data blob.
+fn parse_sshsig(data: &[u8]) -> DynError<String> {
+    let mut reader = Reader::new(data);
+    if reader.bytes(SSHSIG_MAGIC.len())? != SSHSIG_MAGIC {
+        return Err(anyhow!("Error: not an SSHSIG blob").into());
+    }
+
+    let namespace = reader.str()?.to_string();
+    let _reserved = reader.string()?;
+    let _hash_alg = reader.str()?;
+    let _hash = reader.string()?;
+
+    if !reader.is_empty() || namespace.is_empty() {
+        return Err(anyhow!("Error: malformed SSHSIG blob").into());
+    }
+
+    return Ok(namespace);
+}
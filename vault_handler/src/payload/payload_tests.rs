@@ -0,0 +1,61 @@
+use super::{Payload, SSH_MSG_USERAUTH_REQUEST, HOSTBOUND_METHOD};
+use socket_stdinout::agent::{put_string, put_u8, put_bool};
+
+fn userauth(method: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    put_string(&mut data, b"session id");
+    put_u8(&mut data, SSH_MSG_USERAUTH_REQUEST);
+    put_string(&mut data, "git");
+    put_string(&mut data, "ssh-connection");
+    put_string(&mut data, method);
+    put_bool(&mut data, true);
+    put_string(&mut data, "ssh-ed25519");
+    put_string(&mut data, b"key blob");
+    if method == HOSTBOUND_METHOD {
+        put_string(&mut data, b"host key");
+    }
+    return data;
+}
+
+fn sshsig(namespace: &str) -> Vec<u8> {
+    let mut data = b"SSHSIG".to_vec();
+    put_string(&mut data, namespace);
+    put_string(&mut data, "");
+    put_string(&mut data, "sha512");
+    put_string(&mut data, [0u8; 64]);
+    return data;
+}
+
+#[test]
+fn userauth_is_classified() {
+    let data = userauth("publickey");
+    let Payload::Userauth(auth) = Payload::classify(&data) else {
+        panic!("publickey userauth request wasn't recognised");
+    };
+    assert_eq!(auth.session_id, b"session id");
+    assert_eq!(auth.user, "git");
+    assert_eq!(auth.service, "ssh-connection");
+    assert_eq!(auth.alg, "ssh-ed25519");
+    assert!(auth.host_key.is_none());
+
+    let data = userauth(HOSTBOUND_METHOD);
+    let Payload::Userauth(auth) = Payload::classify(&data) else {
+        panic!("hostbound userauth request wasn't recognised");
+    };
+    assert_eq!(auth.host_key, Some(&b"host key"[..]));
+}
+
+#[test]
+fn sshsig_namespace_is_classified() {
+    assert_eq!(Payload::classify(&sshsig("git")).class(), "sshsig:git");
+}
+
+#[test]
+fn anything_else_is_unknown() {
+    let mut trailing = userauth("publickey");
+    trailing.push(0);
+
+    assert_eq!(Payload::classify(&trailing).class(), "unknown");
+    assert_eq!(Payload::classify(&sshsig("")).class(), "unknown");
+    assert_eq!(Payload::classify(b"arbitrary bytes").class(), "unknown");
+}
@@ -0,0 +1,74 @@
+#[cfg(test)]
+mod policy_tests;
+
+use crate::{
+    config::{KeyRules, Action},
+    session::Session,
+    known_hosts::KnownHosts,
+    payload::Payload,
+};
+
+/// Destination restriction of a key: with allow-hosts set the request
+/// has to be session-bound to one of those hosts and the data has to be
+/// a userauth request for that very session.
+pub fn check_dest(
+    rules: &KeyRules,
+    session: &Session,
+    known_hosts: &KnownHosts,
+    payload: &Payload,
+) -> Result<(), &'static str> {
+    let Some(ref hosts) = rules.allow_hosts else {
+        return Ok(());
+    };
+
+    let Some(dest) = session.dest() else {
+        return Err("key is restricted to hosts but the request isn't session-bound");
+    };
+
+    if !known_hosts.host_allowed(hosts, &dest.host_key) {
+        return Err("destination host isn't allowed for this key");
+    }
+
+    // anything but a userauth request for the bound session could be
+    // replayed against a host the key isn't meant for.
+    let Payload::Userauth(userauth) = payload else {
+        return Err("key is restricted to hosts and only signs userauth requests");
+    };
+
+    if userauth.session_id != dest.session_id
+        || userauth.host_key.is_some_and(|key| key != dest.host_key)
+    {
+        return Err("signed data doesn't belong to the bound session");
+    }
+
+    return Ok(());
+}
+
+/// What to do with a request arriving through a forwarded agent,
+/// requests from the client VM itself are always allowed here.
+pub fn forwarding_action(rules: &KeyRules, session: &Session) -> Action {
+    if !session.forwarded() {
+        return Action::Allow;
+    }
+    return rules.forwarding.unwrap_or(Action::Allow);
+}
+
+/// The sign rule for the payload class. Without rules userauth and
+/// SSHSIG are allowed and anything else is refused, so the vault isn't a
+/// signing oracle for arbitrary data.
+pub fn sign_action(rules: &KeyRules, payload: &Payload) -> Action {
+    let Some(ref sign) = rules.sign else {
+        match payload {
+            Payload::Unknown => return Action::Deny,
+            _ => return Action::Allow,
+        }
+    };
+
+    let class = payload.class();
+    return sign.iter()
+        .find(|(rule, _)| {
+            *rule == class || (rule == "sshsig" && matches!(payload, Payload::Sshsig { .. }))
+        })
+        .map(|(_, action)| *action)
+        .unwrap_or(Action::Deny);
+}
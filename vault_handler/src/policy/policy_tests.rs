@@ -0,0 +1,151 @@
+use super::{check_dest, forwarding_action, sign_action};
+use crate::{
+    config::{KeyRules, Action},
+    encoding::base64_decode,
+    known_hosts::KnownHosts,
+    payload::{Payload, Userauth},
+    session::{Session, Bind},
+};
+
+const GITHUB_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAAUE=";
+const OTHER_KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAAUI=";
+
+fn userauth<'a>(session_id: &'a [u8], host_key: Option<&'a [u8]>) -> Payload<'a> {
+    return Payload::Userauth(Userauth {
+        session_id,
+        user: "git".to_string(),
+        service: "ssh-connection".to_string(),
+        alg: "ssh-ed25519".to_string(),
+        host_key,
+    });
+}
+
+fn sshsig(namespace: &str) -> Payload<'static> {
+    return Payload::Sshsig { namespace: namespace.to_string() };
+}
+
+fn known_hosts() -> KnownHosts {
+    let mut known_hosts = KnownHosts::default();
+    known_hosts.add(&format!("\
+        github.com ssh-ed25519 {GITHUB_KEY}\n\
+        example.org ssh-ed25519 {OTHER_KEY}\n\
+    "));
+    return known_hosts;
+}
+
+fn bound_to(host_key: &[u8], forwarding: bool) -> Session {
+    let mut session = Session::default();
+    session.bind(Bind {
+        host_key: host_key.to_vec(),
+        session_id: b"session id".to_vec(),
+        forwarding,
+    });
+    return session;
+}
+
+fn github_only() -> KeyRules {
+    return KeyRules {
+        allow_hosts: Some(vec!["github.com".to_string()]),
+        ..KeyRules::default()
+    };
+}
+
+fn sign_rules(rules: &[(&str, Action)]) -> KeyRules {
+    return KeyRules {
+        sign: Some(rules.iter().map(|(class, action)| (class.to_string(), *action)).collect()),
+        ..KeyRules::default()
+    };
+}
+
+#[test]
+fn unknown_payloads_are_refused_by_default() {
+    let rules = KeyRules::default();
+    assert_eq!(sign_action(&rules, &userauth(b"session id", None)), Action::Allow);
+    assert_eq!(sign_action(&rules, &sshsig("git")), Action::Allow);
+    assert_eq!(sign_action(&rules, &Payload::Unknown), Action::Deny);
+}
+
+#[test]
+fn sshsig_rule_matches_every_namespace() {
+    let rules = sign_rules(&[("sshsig:git", Action::Confirm), ("sshsig", Action::Allow)]);
+    assert_eq!(sign_action(&rules, &sshsig("git")), Action::Confirm);
+    assert_eq!(sign_action(&rules, &sshsig("file")), Action::Allow);
+}
+
+#[test]
+fn unlisted_payloads_are_refused_once_rules_exist() {
+    let rules = sign_rules(&[("sshsig:git", Action::Allow), ("unknown", Action::Confirm)]);
+    assert_eq!(sign_action(&rules, &userauth(b"session id", None)), Action::Deny);
+    assert_eq!(sign_action(&rules, &sshsig("file")), Action::Deny);
+    assert_eq!(sign_action(&rules, &Payload::Unknown), Action::Confirm);
+}
+
+#[test]
+fn bound_userauth_to_an_allowed_host_passes() {
+    let (rules, known_hosts) = (github_only(), known_hosts());
+    let github = base64_decode(GITHUB_KEY).unwrap();
+    let session = bound_to(&github, false);
+
+    assert!(check_dest(&rules, &session, &known_hosts, &userauth(b"session id", None)).is_ok());
+    assert!(check_dest(&rules, &session, &known_hosts,
+        &userauth(b"session id", Some(&github))).is_ok());
+    // keys without allow-hosts aren't restricted at all
+    assert!(check_dest(&KeyRules::default(), &Session::default(), &known_hosts,
+        &Payload::Unknown).is_ok());
+}
+
+#[test]
+fn restricted_keys_need_a_session_bind() {
+    let payload = userauth(b"session id", None);
+    let checked = check_dest(&github_only(), &Session::default(), &known_hosts(), &payload);
+    assert_eq!(checked, Err("key is restricted to hosts but the request isn't session-bound"));
+}
+
+#[test]
+fn other_hosts_are_refused() {
+    let other = base64_decode(OTHER_KEY).unwrap();
+    let payload = userauth(b"session id", None);
+    let checked = check_dest(&github_only(), &bound_to(&other, false), &known_hosts(), &payload);
+    assert_eq!(checked, Err("destination host isn't allowed for this key"));
+}
+
+#[test]
+fn revoked_host_keys_are_refused() {
+    let mut known_hosts = known_hosts();
+    known_hosts.add(&format!("@revoked * ssh-ed25519 {GITHUB_KEY}"));
+    let github = base64_decode(GITHUB_KEY).unwrap();
+    let payload = userauth(b"session id", None);
+
+    let checked = check_dest(&github_only(), &bound_to(&github, false), &known_hosts, &payload);
+    assert_eq!(checked, Err("destination host isn't allowed for this key"));
+}
+
+#[test]
+fn data_of_another_session_is_refused() {
+    let (rules, known_hosts) = (github_only(), known_hosts());
+    let (github, other) = (base64_decode(GITHUB_KEY).unwrap(), base64_decode(OTHER_KEY).unwrap());
+    let session = bound_to(&github, false);
+    let mismatch = Err("signed data doesn't belong to the bound session");
+
+    let checked = check_dest(&rules, &session, &known_hosts, &userauth(b"other session", None));
+    assert_eq!(checked, mismatch);
+    let checked = check_dest(&rules, &session, &known_hosts,
+        &userauth(b"session id", Some(&other)));
+    assert_eq!(checked, mismatch);
+    let checked = check_dest(&rules, &session, &known_hosts, &sshsig("git"));
+    assert_eq!(checked, Err("key is restricted to hosts and only signs userauth requests"));
+}
+
+#[test]
+fn forwarded_sessions_follow_the_forwarding_rule() {
+    let github = base64_decode(GITHUB_KEY).unwrap();
+    let (local, forwarded) = (bound_to(&github, false), bound_to(&github, true));
+    let with = |forwarding| KeyRules { forwarding, ..KeyRules::default() };
+
+    for action in [Action::Allow, Action::Deny, Action::Confirm] {
+        assert_eq!(forwarding_action(&with(Some(action)), &forwarded), action);
+        // requests from the client VM itself aren't forwarded
+        assert_eq!(forwarding_action(&with(Some(action)), &local), Action::Allow);
+    }
+    assert_eq!(forwarding_action(&with(None), &forwarded), Action::Allow);
+}
@@ -0,0 +1,70 @@
+use std::{
+    env,
+    process::{Command, Stdio},
+};
+
+const ASKPASS_VAR: &str = "SSH_ASKPASS";
+const DEFAULT_ASKPASS: &str = "ssh-askpass";
+const DISPLAY_VAR: &str = "DISPLAY";
+/// qrexec services don't inherit the GUI session's environment
+const DEFAULT_DISPLAY: &str = ":0";
+
+/// The askpass program, as used by ssh-agent for its own confirmations:
+/// the configured one, $SSH_ASKPASS or ssh-askpass from $PATH.
+fn askpass_program(askpass: Option<&str>) -> String {
+    return askpass.map(str::to_string)
+        .or_else(|| env::var(ASKPASS_VAR).ok())
+        .unwrap_or(DEFAULT_ASKPASS.to_string());
+}
+
+fn askpass_cmd(askpass: Option<&str>) -> Command {
+    let mut cmd = Command::new(askpass_program(askpass));
+    if env::var_os(DISPLAY_VAR).is_none() {
+        cmd.env(DISPLAY_VAR, DEFAULT_DISPLAY);
+    }
+    return cmd;
+}
+
+/// Makes an ssh tool run by cmd, e.g. ssh-add, ask for passphrases
+/// through the same askpass program instead of a terminal.
+pub fn use_askpass(cmd: &mut Command, askpass: Option<&str>) {
+    cmd.env(ASKPASS_VAR, askpass_program(askpass))
+        .env("SSH_ASKPASS_REQUIRE", "prefer");
+    if env::var_os(DISPLAY_VAR).is_none() {
+        cmd.env(DISPLAY_VAR, DEFAULT_DISPLAY);
+    }
+}
+
+/// Asks the user in the vault to approve, false if they refuse or the
+/// askpass program can't be run.
+pub fn confirm(askpass: Option<&str>, msg: &str) -> bool {
+    return askpass_cmd(askpass)
+        .env("SSH_ASKPASS_PROMPT", "confirm")
+        .arg(msg)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false);
+}
+
+/// Asks the user in the vault for a passphrase, None if they cancel or
+/// the askpass program can't be run.
+#[cfg(feature = "builtin-agent")]
+pub fn passphrase(askpass: Option<&str>, msg: &str) -> Option<zeroize::Zeroizing<Vec<u8>>> {
+    let output = askpass_cmd(askpass)
+        .arg(msg)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+
+    let mut passphrase = zeroize::Zeroizing::new(output.stdout);
+    if !output.status.success() {
+        return None;
+    }
+    if passphrase.last() == Some(&b'\n') {
+        passphrase.pop();
+    }
+    return Some(passphrase);
+}
@@ -0,0 +1,23 @@
+use crate::{
+    state,
+    audit::timestamp,
+};
+
+use socket_stdinout::types::DynError;
+use std::{
+    fs,
+    time::SystemTime,
+};
+
+const RELOCK_FNAME: &str = "relock";
+
+/// The file `vault_handler relock` rewrites, every vault_handler with an
+/// unlocked keystore relocks it once the file is newer than the unlock.
+pub fn path() -> DynError<String> {
+    return state::path(RELOCK_FNAME);
+}
+
+pub fn request() -> DynError<()> {
+    fs::write(path()?, format!("{}\n", timestamp(SystemTime::now())))?;
+    return Ok(());
+}
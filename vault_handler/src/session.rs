@@ -0,0 +1,89 @@
+#[cfg(test)]
+mod session_tests;
+
+use crate::{
+    keys,
+    encoding::hex,
+};
+
+use socket_stdinout::{
+    agent::Reader,
+    types::DynError,
+};
+use anyhow::anyhow;
+
+pub const SESSION_BIND_EXT: &str = "session-bind@openssh.com";
+/// ssh-agent refuses more binds than this on a single connection
+const MAX_BINDS: usize = 16;
+
+/// A session-bind@openssh.com the agent accepted: the client proved the
+/// session identifier belongs to a connection with this server host key.
+#[derive(Clone)]
+pub struct Bind {
+    pub host_key: Vec<u8>,
+    pub session_id: Vec<u8>,
+    pub forwarding: bool,
+}
+
+impl Bind {
+    /// parses the extension contents following the extension name.
+    pub fn parse(contents: &[u8]) -> DynError<Self> {
+        let mut reader = Reader::new(contents);
+        let host_key = reader.string()?.to_vec();
+        let session_id = reader.string()?.to_vec();
+        let _signature = reader.string()?;
+        let forwarding = reader.bool()?;
+
+        if !reader.is_empty() {
+            return Err(anyhow!("Error: trailing data after session-bind").into());
+        }
+
+        return Ok(Self { host_key, session_id, forwarding });
+    }
+}
+
+/// What the vault knows about the ssh connection on the other end of the
+/// current agent connection. Reset whenever the client side reconnects.
+#[derive(Default)]
+pub struct Session {
+    binds: Vec<Bind>,
+}
+
+impl Session {
+    pub fn bind(&mut self, bind: Bind) {
+        if self.binds.len() < MAX_BINDS {
+            self.binds.push(bind);
+        }
+    }
+
+    /// The host the client is authenticating to: the last bind, earlier
+    /// ones are the hops an agent forwarding chain went through.
+    pub fn dest(&self) -> Option<&Bind> {
+        return self.binds.last();
+    }
+
+    /// Fingerprint of the destination host key.
+    pub fn dest_fingerprint(&self) -> Option<String> {
+        return self.dest().map(|bind| keys::fingerprint(&bind.host_key));
+    }
+
+    /// true if any hop was bound as a forwarded agent connection, i.e.
+    /// the request reaches us through `ssh -A` on some remote host.
+    pub fn forwarded(&self) -> bool {
+        return self.binds.iter().any(|bind| bind.forwarding);
+    }
+
+    /// The remote host the agent was forwarded to, the one `ssh -A` was
+    /// run against.
+    pub fn forwarded_via(&self) -> Option<&Bind> {
+        return self.binds.iter().rev().find(|bind| bind.forwarding);
+    }
+
+    /// short hex prefix of the destination session identifier, enough to
+    /// tell the requests of one ssh session apart in the audit log.
+    pub fn dest_session(&self) -> Option<String> {
+        return self.dest().map(|bind| {
+            hex(&bind.session_id[..bind.session_id.len().min(8)])
+        });
+    }
+}
@@ -0,0 +1,34 @@
+use super::{Bind, Session};
+use socket_stdinout::agent::{put_string, put_bool};
+
+fn bind_contents(host_key: &[u8], forwarding: bool) -> Vec<u8> {
+    let mut contents = Vec::new();
+    put_string(&mut contents, host_key);
+    put_string(&mut contents, b"session id");
+    put_string(&mut contents, b"signature");
+    put_bool(&mut contents, forwarding);
+    return contents;
+}
+
+#[test]
+fn session_bind_parses() {
+    let bind = Bind::parse(&bind_contents(b"host key", true)).unwrap();
+    assert_eq!(bind.host_key, b"host key");
+    assert_eq!(bind.session_id, b"session id");
+    assert!(bind.forwarding);
+
+    let mut trailing = bind_contents(b"host key", false);
+    trailing.push(0);
+    assert!(Bind::parse(&trailing).is_err(), "trailing data was accepted");
+}
+
+#[test]
+fn forwarded_chain_targets_last_hop() {
+    let mut session = Session::default();
+    assert!(session.dest().is_none());
+
+    session.bind(Bind::parse(&bind_contents(b"jump host", true)).unwrap());
+    session.bind(Bind::parse(&bind_contents(b"dest host", false)).unwrap());
+    assert!(session.forwarded());
+    assert_eq!(session.dest().unwrap().host_key, b"dest host");
+}
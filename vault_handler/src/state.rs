@@ -0,0 +1,45 @@
+use socket_stdinout::{
+    ERR_LOG_DIR_NAME,
+    debug::get_xdg_state_dir,
+    types::DynError,
+};
+use std::{
+    fs,
+    io::{Read, Seek, Write},
+};
+
+/// path of fname in the split-ssh state directory, which is created if
+/// it doesn't exist yet.
+pub fn path(fname: &str) -> DynError<String> {
+    let dir = get_xdg_state_dir(ERR_LOG_DIR_NAME)?;
+    fs::create_dir_all(&dir)?;
+    return Ok(format!("{dir}/{fname}"));
+}
+
+/// Runs f on the contents of a state file shared by every
+/// vault_handler process and writes the result back if f changed it.
+/// The file stays locked while f runs.
+pub fn update<T>(
+    path: &str,
+    f: impl FnOnce(&mut String) -> DynError<T>,
+) -> DynError<T> {
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    file.lock()?;
+
+    let mut text = String::new();
+    file.read_to_string(&mut text)?;
+    let before = text.clone();
+
+    let res = f(&mut text);
+    if text != before {
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(text.as_bytes())?;
+    }
+    return res;
+}
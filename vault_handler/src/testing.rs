@@ -0,0 +1,37 @@
+use std::{
+    fs,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A fresh directory for one test under the system's temp directory,
+/// removed with everything in it when dropped.
+pub struct TempDir(String);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        // tests of one process run in parallel and may share a name
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = format!("{}/split-ssh-{name}-{}-{}",
+            std::env::temp_dir().display(),
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        return Self(path);
+    }
+
+    pub fn path(&self) -> &str {
+        return &self.0;
+    }
+
+    /// path of fname inside the directory.
+    pub fn join(&self, fname: &str) -> String {
+        return format!("{}/{fname}", self.0);
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
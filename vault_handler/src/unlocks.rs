@@ -0,0 +1,120 @@
+#[cfg(test)]
+mod unlocks_tests;
+
+use crate::{
+    state,
+    known_hosts::wildcard_match,
+};
+
+use socket_stdinout::types::DynError;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const UNLOCKS_FNAME: &str = "unlocks";
+
+/// A key, by fingerprint or comment, usable by the domains matching
+/// domain until the unix time until.
+struct Window {
+    until: u64,
+    domain: String,
+    key: String,
+}
+
+/// Unlock windows opened with `vault_handler unlock`, kept in the state
+/// directory as `<until> <domain> <key>` lines so every vault_handler
+/// process sees them. Expired windows are dropped on every change.
+pub struct Unlocks {
+    path: String,
+}
+
+impl Unlocks {
+    pub fn new() -> DynError<Self> {
+        return Ok(Self { path: state::path(UNLOCKS_FNAME)? });
+    }
+
+    #[cfg(test)]
+    pub fn with_path(path: &str) -> Self {
+        return Self { path: path.to_string() };
+    }
+
+    fn update<T>(
+        &self,
+        now: SystemTime,
+        f: impl FnOnce(&mut Vec<Window>) -> T,
+    ) -> DynError<T> {
+        let now = unix_secs(now);
+        return state::update(&self.path, |text| {
+            let mut windows: Vec<Window> = text.lines()
+                .filter_map(parse)
+                .filter(|window| window.until > now)
+                .collect();
+
+            let res = f(&mut windows);
+            *text = windows.iter()
+                .map(|w| format!("{} {} {}\n", w.until, w.domain, w.key))
+                .collect();
+            return Ok(res);
+        });
+    }
+
+    /// Opens a window until the given time, replacing any earlier one
+    /// for the same key and domain.
+    pub fn unlock(
+        &self,
+        key: &str,
+        domain: &str,
+        until: SystemTime,
+    ) -> DynError<()> {
+        return self.update(SystemTime::now(), |windows| {
+            windows.retain(|w| w.key != key || w.domain != domain);
+            windows.push(Window {
+                until: unix_secs(until),
+                domain: domain.to_string(),
+                key: key.to_string(),
+            });
+        });
+    }
+
+    /// Closes the key's windows, for every domain if domain is None, and
+    /// returns how many were open.
+    pub fn lock(&self, key: &str, domain: Option<&str>) -> DynError<usize> {
+        return self.update(SystemTime::now(), |windows| {
+            let before = windows.len();
+            windows.retain(|w| w.key != key || domain.is_some_and(|d| d != w.domain));
+            return before - windows.len();
+        });
+    }
+
+    /// Closes every window, returns how many were open.
+    pub fn clear(&self) -> DynError<usize> {
+        return self.update(SystemTime::now(), |windows| {
+            return windows.drain(..).count();
+        });
+    }
+
+    pub fn is_unlocked(
+        &self,
+        fingerprint: &str,
+        comment: Option<&str>,
+        domain: &str,
+        now: SystemTime,
+    ) -> DynError<bool> {
+        return self.update(now, |windows| {
+            return windows.iter().any(|w| {
+                (w.key == fingerprint || Some(w.key.as_str()) == comment)
+                    && wildcard_match(&w.domain, domain)
+            });
+        });
+    }
+}
+
+fn parse(line: &str) -> Option<Window> {
+    let mut fields = line.splitn(3, ' ');
+    let until = fields.next()?.parse().ok()?;
+    let domain = fields.next()?.to_string();
+    let key = fields.next()?.to_string();
+    return Some(Window { until, domain, key });
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    return time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+}
@@ -0,0 +1,38 @@
+use super::Unlocks;
+use crate::testing::TempDir;
+use std::time::{Duration, SystemTime};
+
+fn unlocks_at(name: &str) -> (Unlocks, TempDir) {
+    let dir = TempDir::new(&format!("unlocks-{name}"));
+    return (Unlocks::with_path(&dir.join("unlocks")), dir);
+}
+
+#[test]
+fn window_covers_key_and_domain_until_expiry() {
+    let (unlocks, _dir) = unlocks_at("window");
+    let now = SystemTime::now();
+    unlocks.unlock("deploy key", "work", now + Duration::from_secs(600)).unwrap();
+
+    assert!(unlocks.is_unlocked("SHA256:x", Some("deploy key"), "work", now).unwrap());
+    assert!(!unlocks.is_unlocked("SHA256:x", Some("deploy key"), "dev", now).unwrap());
+    assert!(!unlocks.is_unlocked("SHA256:x", Some("other"), "work", now).unwrap());
+
+    let later = now + Duration::from_secs(601);
+    assert!(!unlocks.is_unlocked("SHA256:x", Some("deploy key"), "work", later).unwrap());
+}
+
+#[test]
+fn lock_closes_windows() {
+    let (unlocks, _dir) = unlocks_at("lock");
+    let until = SystemTime::now() + Duration::from_secs(600);
+    unlocks.unlock("SHA256:x", "work", until).unwrap();
+    unlocks.unlock("SHA256:x", "dev", until).unwrap();
+
+    assert_eq!(unlocks.lock("SHA256:x", Some("work")).unwrap(), 1);
+    let now = SystemTime::now();
+    assert!(!unlocks.is_unlocked("SHA256:x", None, "work", now).unwrap());
+    assert!(unlocks.is_unlocked("SHA256:x", None, "dev", now).unwrap());
+
+    assert_eq!(unlocks.lock("SHA256:x", None).unwrap(), 1);
+    assert!(!unlocks.is_unlocked("SHA256:x", None, "dev", now).unwrap());
+}
@@ -0,0 +1,179 @@
+#[cfg(test)]
+mod usage_tests;
+
+use crate::{
+    state,
+    encoding::escape,
+};
+
+use socket_stdinout::types::DynError;
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::anyhow;
+
+const USAGE_FNAME: &str = "usage";
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// Signatures one key made for one domain.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Counter {
+    pub key: String,
+    pub domain: String,
+    /// the UTC day, in days since the epoch, today counts for
+    pub day: u64,
+    pub today: u64,
+    pub total: u64,
+    /// unix time of the last signature
+    pub last: u64,
+    /// the key's comment as of the last signature, escaped
+    pub comment: String,
+}
+
+impl Counter {
+    /// Signatures made on the day of now.
+    pub fn today(&self, now: SystemTime) -> u64 {
+        if self.day == day(now) {
+            return self.today;
+        } else {
+            return 0;
+        }
+    }
+}
+
+/// Per key and domain signature counters, kept in the state directory as
+/// `<fingerprint> <domain> <day> <today> <total> <last> <comment>` lines
+/// so every vault_handler process counts into the same file. The comment
+/// comes from the agent and is escaped so it stays on its line.
+pub struct Usage {
+    path: String,
+}
+
+impl Usage {
+    pub fn new() -> DynError<Self> {
+        return Ok(Self { path: state::path(USAGE_FNAME)? });
+    }
+
+    #[cfg(test)]
+    pub fn with_path(path: &str) -> Self {
+        return Self { path: path.to_string() };
+    }
+
+    /// Counts a signature the key is about to make for the domain at
+    /// now, if check passes it given the signatures the key and the
+    /// domain made today. Checking and counting happen under the one
+    /// lock, so two requests can't both take the last signature of a
+    /// quota.
+    pub fn reserve<T, E>(
+        &self,
+        fingerprint: &str,
+        comment: Option<&str>,
+        domain: &str,
+        now: SystemTime,
+        check: impl FnOnce(u64, u64) -> Result<T, E>,
+    ) -> DynError<Result<T, E>> {
+        return state::update(&self.path, |text| {
+            let mut counters = parse(text, &self.path)?;
+            let key_today = counters.iter()
+                .filter(|c| c.key == fingerprint)
+                .map(|c| c.today(now))
+                .sum();
+            let domain_today = counters.iter()
+                .filter(|c| c.domain == domain)
+                .map(|c| c.today(now))
+                .sum();
+            let checked = check(key_today, domain_today);
+            if checked.is_err() {
+                return Ok(checked);
+            }
+
+            let counter = counter(&mut counters, fingerprint, domain, now);
+            counter.today = counter.today(now) + 1;
+            counter.day = day(now);
+            counter.total += 1;
+            counter.last = unix_secs(now);
+            if let Some(comment) = comment {
+                counter.comment = escape(comment);
+            }
+            *text = to_text(&counters);
+            return Ok(checked);
+        });
+    }
+
+    /// Takes back a signature reserved at now that wasn't made after all.
+    pub fn release(&self, fingerprint: &str, domain: &str, now: SystemTime) -> DynError<()> {
+        return state::update(&self.path, |text| {
+            let mut counters = parse(text, &self.path)?;
+            let counter = counter(&mut counters, fingerprint, domain, now);
+            if counter.day == day(now) {
+                counter.today = counter.today.saturating_sub(1);
+            }
+            counter.total = counter.total.saturating_sub(1);
+            *text = to_text(&counters);
+            return Ok(());
+        });
+    }
+
+    pub fn counters(&self) -> DynError<Vec<Counter>> {
+        return state::update(&self.path, |text| parse(text, &self.path));
+    }
+}
+
+/// the counter of the key for the domain, a new one if there is none.
+fn counter<'a>(
+    counters: &'a mut Vec<Counter>,
+    fingerprint: &str,
+    domain: &str,
+    now: SystemTime,
+) -> &'a mut Counter {
+    let index = match counters.iter().position(|c| c.key == fingerprint && c.domain == domain) {
+        Some(index) => index,
+        None => {
+            counters.push(Counter {
+                key: fingerprint.to_string(),
+                domain: domain.to_string(),
+                day: day(now),
+                today: 0,
+                total: 0,
+                last: 0,
+                comment: String::new(),
+            });
+            counters.len() - 1
+        }
+    };
+    return &mut counters[index];
+}
+
+/// A line that doesn't parse is an error rather than dropped, dropping
+/// it would start the counters over.
+fn parse(text: &str, path: &str) -> DynError<Vec<Counter>> {
+    return text.lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let mut fields = line.splitn(7, ' ');
+            let counter = (|| Some(Counter {
+                key: fields.next()?.to_string(),
+                domain: fields.next()?.to_string(),
+                day: fields.next()?.parse().ok()?,
+                today: fields.next()?.parse().ok()?,
+                total: fields.next()?.parse().ok()?,
+                last: fields.next()?.parse().ok()?,
+                comment: fields.next()?.to_string(),
+            }))();
+            return counter.ok_or_else(|| anyhow!("Error: {path}: line {} is malformed", i + 1).into());
+        })
+        .collect();
+}
+
+fn to_text(counters: &[Counter]) -> String {
+    return counters.iter()
+        .map(|c| format!("{} {} {} {} {} {} {}\n",
+            c.key, c.domain, c.day, c.today, c.total, c.last, c.comment))
+        .collect();
+}
+
+fn day(time: SystemTime) -> u64 {
+    return unix_secs(time) / DAY_SECS;
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    return time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+}
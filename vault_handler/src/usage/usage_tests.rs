@@ -0,0 +1,96 @@
+use super::Usage;
+use crate::testing::TempDir;
+use std::{
+    fs,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+fn usage_at(name: &str) -> (Usage, TempDir) {
+    let dir = TempDir::new(&format!("usage-{name}"));
+    return (Usage::with_path(&dir.join("usage")), dir);
+}
+
+/// counts a signature without a quota to check.
+fn count(usage: &Usage, fingerprint: &str, comment: Option<&str>, domain: &str, now: SystemTime) {
+    usage.reserve(fingerprint, comment, domain, now, |_, _| Ok::<_, ()>(())).unwrap().unwrap();
+}
+
+#[test]
+fn counts_per_key_and_domain() {
+    let (usage, _dir) = usage_at("count");
+    let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    count(&usage, "SHA256:a", Some("deploy key"), "work", now);
+    count(&usage, "SHA256:a", Some("deploy key"), "work", now);
+    count(&usage, "SHA256:a", None, "dev", now);
+    count(&usage, "SHA256:b", None, "work", now);
+
+    let counters = usage.counters().unwrap();
+    assert_eq!(counters.len(), 3);
+    let work = counters.iter().find(|c| c.key == "SHA256:a" && c.domain == "work").unwrap();
+    assert_eq!((work.today(now), work.total, work.last), (2, 2, 1_700_000_000));
+    assert_eq!(work.comment, "deploy key");
+    let dev = counters.iter().find(|c| c.key == "SHA256:a" && c.domain == "dev").unwrap();
+    assert_eq!((dev.today(now), dev.total, dev.comment.as_str()), (1, 1, ""));
+}
+
+#[test]
+fn today_starts_over_every_day() {
+    let (usage, _dir) = usage_at("day");
+    let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let tomorrow = now + Duration::from_secs(24 * 60 * 60);
+    count(&usage, "SHA256:a", None, "work", now);
+    count(&usage, "SHA256:a", None, "work", now);
+
+    let counter = usage.counters().unwrap().remove(0);
+    assert_eq!((counter.today(now), counter.today(tomorrow)), (2, 0));
+
+    count(&usage, "SHA256:a", None, "work", tomorrow);
+    let counter = usage.counters().unwrap().remove(0);
+    assert_eq!((counter.today(tomorrow), counter.total), (1, 3));
+}
+
+#[test]
+fn quota_checks_see_the_counts_and_refusals_dont_count() {
+    let (usage, _dir) = usage_at("reserve");
+    let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    count(&usage, "SHA256:a", None, "work", now);
+    count(&usage, "SHA256:b", None, "work", now);
+
+    let checked = usage.reserve("SHA256:a", None, "dev", now, |key_today, domain_today| {
+        assert_eq!((key_today, domain_today), (1, 0));
+        return Err::<(), _>("over quota");
+    });
+    assert_eq!(checked.unwrap(), Err("over quota"));
+    assert_eq!(usage.counters().unwrap().len(), 2);
+
+    count(&usage, "SHA256:a", None, "work", now);
+    usage.release("SHA256:a", "work", now).unwrap();
+    let counter = usage.counters().unwrap().remove(0);
+    assert_eq!((counter.today(now), counter.total), (1, 1));
+}
+
+#[test]
+fn comments_stay_on_their_line() {
+    let (usage, _dir) = usage_at("comment");
+    let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    count(&usage, "SHA256:a", Some("key\nSHA256:b work 19675 0 0 0 forged"), "work", now);
+
+    let counters = usage.counters().unwrap();
+    assert_eq!(counters.len(), 1);
+    assert_eq!(counters[0].comment, "key\\nSHA256:b work 19675 0 0 0 forged");
+}
+
+#[test]
+fn malformed_lines_are_refused() {
+    let (usage, dir) = usage_at("malformed");
+    let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    count(&usage, "SHA256:a", None, "work", now);
+    let path = dir.join("usage");
+    let mut text = fs::read_to_string(&path).unwrap();
+    text.push_str("SHA256:b work soon\n");
+    fs::write(&path, text).unwrap();
+
+    let e = usage.counters().unwrap_err().to_string();
+    assert!(e.ends_with("line 2 is malformed"), "{e}");
+    assert!(usage.reserve("SHA256:a", None, "work", now, |_, _| Ok::<_, ()>(())).is_err());
+}
@@ -0,0 +1,975 @@
+#[cfg(test)]
+mod vault_tests;
+
+use crate::{
+    alert,
+    anomaly::{self, Baseline, KeyUse},
+    cert::{self, Cert},
+    discover,
+    encoding::escape,
+    kill,
+    audit::{Audit, Record, timestamp},
+    import::NewKey,
+    owners::{Owners, Owned, owner},
+    unlocks::Unlocks,
+    usage::Usage,
+    config::{Config, ArgumentRules, Action, RsaSha1, Comments, DomainRules},
+    known_hosts::{KnownHosts, wildcard_match},
+    payload::Payload,
+    keys,
+    policy,
+    prompt,
+    session::{Session, Bind, SESSION_BIND_EXT},
+};
+#[cfg(feature = "builtin-agent")]
+use crate::{
+    builtin::Builtin,
+    ca::{Ca, CertRequest},
+};
+
+use socket_stdinout::{
+    ERR_LOG_DIR_NAME,
+    debug::append,
+    agent::{self, msg, Identity, Reader, flags::RSA_SHA2_512},
+    filter::{AgentFilter, AgentConn},
+    types::DynError,
+};
+use std::{
+    fs,
+    env,
+    time::{Instant, SystemTime},
+};
+#[cfg(feature = "builtin-agent")]
+use std::time::Duration;
+#[cfg(feature = "builtin-agent")]
+use socket_stdinout::agent::CERT_REQUEST_EXT;
+#[cfg(feature = "builtin-agent")]
+use ssh_key::PublicKey;
+
+pub const DOMAIN_VAR: &str = "QREXEC_REMOTE_DOMAIN";
+/// the `work` of `qubes.SplitSSHAgent+work`, empty without an argument
+const ARGUMENT_VAR: &str = "QREXEC_SERVICE_ARGUMENT";
+const UNKNOWN_DOMAIN: &str = "unknown";
+const LEGACY_SHA1_FNAME: &str = "legacy-sha1.log";
+const HOSTNAME_PATH: &str = "/etc/hostname";
+/// answered by the vault itself with the extensions the domain may use
+const QUERY_EXT: &str = "query";
+/// seconds, for domains with `import confirm` and no import-lifetime
+const IMPORT_LIFETIME: u32 = 3600;
+const IMPORT_MIN_RSA_BITS: u32 = 3072;
+#[cfg(feature = "builtin-agent")]
+const KEYSTORE_IDLE: u32 = 300;
+/// seconds, for domains with cert-principals and no cert-validity
+#[cfg(feature = "builtin-agent")]
+const CERT_VALIDITY: u32 = 300;
+
+/// The vault side AgentFilter, every request from the client domain
+/// passes through here and ends up as one audit record.
+pub struct Vault {
+    domain: String,
+    argument: Option<String>,
+    /// the config's argument blocks for the service argument
+    argument_rules: ArgumentRules,
+    /// the agent the domain and argument are routed to, also recorded
+    /// with the keys in the owners file, builtin:<dir> for the built-in
+    /// agent
+    agent_socket: String,
+    /// the built-in agent's key directory, used instead of a socket
+    #[cfg(feature = "builtin-agent")]
+    builtin_keys: Option<String>,
+    /// the certificate authority, if there's a ca-key
+    #[cfg(feature = "builtin-agent")]
+    ca: Option<Ca>,
+    audit: Audit,
+    config: Config,
+    /// the config's domain blocks for this client domain
+    domain_rules: DomainRules,
+    known_hosts: KnownHosts,
+    /// the agent's keys as of the last REQUEST_IDENTITIES on this
+    /// connection, including those the domain doesn't get to see
+    identities: Option<Vec<Identity>>,
+    session: Session,
+    owners: Owners,
+    /// decoy keys listed to the domain, a request to sign with one
+    /// raises an alert
+    canaries: Vec<Identity>,
+    unlocks: Unlocks,
+    usage: Usage,
+    /// what the keys normally do, signatures that don't fit raise alerts
+    baseline: Baseline,
+    kill_path: String,
+    /// the kill switch was engaged at the last request, or when the vault
+    /// was opened
+    killed: bool,
+}
+
+impl Vault {
+    pub fn new() -> DynError<Self> {
+        let domain = env::var(DOMAIN_VAR)
+            .unwrap_or_else(|_| UNKNOWN_DOMAIN.to_string());
+
+        // a killed vault refuses everything without an agent, so there is
+        // no agent to wait for or to start
+        let kill_path = kill::path()?;
+        let killed = kill::engaged(&kill_path);
+
+        let config = Config::load()?;
+        let known_hosts = KnownHosts::load(&config.known_hosts);
+        let argument = env::var(ARGUMENT_VAR).ok()
+            .filter(|arg| !arg.is_empty());
+        let domain_rules = config.domain_rules(&domain);
+        let argument_rules = config.argument_rules(argument.as_deref().unwrap_or_default());
+        let routed = argument_rules.agent_socket.clone()
+            .or_else(|| domain_rules.agent_socket.clone());
+        #[cfg(feature = "builtin-agent")]
+        let builtin_keys = config.builtin_keys.clone().filter(|_| routed.is_none() && !killed);
+        #[cfg(feature = "builtin-agent")]
+        let agent_socket = match &builtin_keys {
+            Some(dir) => format!("builtin:{dir}"),
+            None if killed => String::new(),
+            None => discover::agent_socket(&config, routed.as_deref())?,
+        };
+        #[cfg(not(feature = "builtin-agent"))]
+        let agent_socket = if killed {
+            String::new()
+        } else {
+            discover::agent_socket(&config, routed.as_deref())?
+        };
+        #[cfg(feature = "builtin-agent")]
+        let ca = config.ca_key.as_deref().map(|path| Ca::new(
+            path,
+            &domain,
+            config.askpass.as_deref(),
+            Duration::from_secs(config.keystore_idle.unwrap_or(KEYSTORE_IDLE).into()),
+        ));
+
+        let mut canaries = Vec::new();
+        for path in domain_rules.canaries.iter().flatten() {
+            canaries.extend(keys::read_public_keys(path)?);
+        }
+
+        return Ok(Self {
+            domain,
+            argument,
+            argument_rules,
+            agent_socket,
+            #[cfg(feature = "builtin-agent")]
+            builtin_keys,
+            #[cfg(feature = "builtin-agent")]
+            ca,
+            audit: Audit::new()?,
+            config,
+            domain_rules,
+            known_hosts,
+            identities: None,
+            session: Session::default(),
+            owners: Owners::new()?,
+            canaries,
+            unlocks: Unlocks::new()?,
+            usage: Usage::new()?,
+            baseline: Baseline::default(),
+            kill_path,
+            killed,
+        });
+    }
+
+    /// A vault for domain with the default config, keeping its state
+    /// files in dir.
+    #[cfg(test)]
+    pub fn with_state_dir(domain: &str, dir: &str) -> Self {
+        let config = Config::default();
+        return Self {
+            domain: domain.to_string(),
+            argument: None,
+            argument_rules: config.argument_rules(""),
+            agent_socket: "test".to_string(),
+            #[cfg(feature = "builtin-agent")]
+            builtin_keys: None,
+            #[cfg(feature = "builtin-agent")]
+            ca: None,
+            audit: Audit::with_dir(dir),
+            domain_rules: config.domain_rules(domain),
+            config,
+            known_hosts: KnownHosts::default(),
+            identities: None,
+            session: Session::default(),
+            owners: Owners::with_path(&format!("{dir}/owners")),
+            canaries: Vec::new(),
+            unlocks: Unlocks::with_path(&format!("{dir}/unlocks")),
+            usage: Usage::with_path(&format!("{dir}/usage")),
+            baseline: Baseline::default(),
+            killed: kill::engaged(&format!("{dir}/killed")),
+            kill_path: format!("{dir}/killed"),
+        };
+    }
+
+    /// The kill switch was engaged when the vault was opened, the
+    /// requests are refused without connecting to an agent then.
+    pub fn killed(&self) -> bool {
+        return self.killed;
+    }
+
+    /// The agent socket to connect to, empty if killed.
+    pub fn agent_socket(&self) -> &str {
+        return &self.agent_socket;
+    }
+
+    /// The built-in agent if the requests go to it instead of a socket.
+    #[cfg(feature = "builtin-agent")]
+    pub fn builtin(&self) -> DynError<Option<Builtin>> {
+        let Some(dir) = &self.builtin_keys else {
+            return Ok(None);
+        };
+
+        let idle = self.config.keystore_idle.unwrap_or(KEYSTORE_IDLE);
+        return Ok(Some(Builtin::load(
+            dir,
+            &self.domain,
+            self.config.askpass.as_deref(),
+            Duration::from_secs(idle.into()),
+        )?));
+    }
+
+    fn dispatch(
+        &mut self,
+        agent: &mut AgentConn,
+        msg: Vec<u8>,
+        record: &mut Record,
+    ) -> DynError<Vec<u8>> {
+        match msg.first() {
+            Some(&msg::REQUEST_IDENTITIES) => return self.identities(agent, msg, record),
+
+            Some(&msg::SIGN_REQUEST) => return self.sign_request(agent, msg, record),
+
+            Some(&msg::EXTENSION) => return self.extension(agent, msg, record),
+
+            Some(&msg::ADD_IDENTITY | &msg::ADD_ID_CONSTRAINED) =>
+                return self.import(agent, msg, record),
+
+            Some(&msg::REMOVE_IDENTITY) => return self.remove(agent, msg, record),
+
+            Some(&msg::REMOVE_ALL_IDENTITIES | &msg::REMOVE_ALL_RSA_IDENTITIES) =>
+                return self.remove_all(agent, record),
+
+            // would load a PKCS#11 provider of the vault into its agent,
+            // past the import opt-in and the key ownership
+            Some(&msg::ADD_SMARTCARD_KEY
+                | &msg::ADD_SMARTCARD_KEY_CONSTRAINED
+                | &msg::REMOVE_SMARTCARD_KEY) =>
+                return Ok(deny(record, "smartcard keys can't be managed from client domains")),
+
+            // the agent is shared, locking it would lock every other
+            // domain out as well
+            Some(&msg::LOCK | &msg::UNLOCK) =>
+                return Ok(deny(record, "the vault agent can't be locked from client domains")),
+
+            _ => return agent.call(&msg),
+        }
+    }
+
+    /// The agent's keys minus those of other domains, those outside of
+    /// the argument's key profile, keys outside of their unlock window
+    /// and certificates outside of their validity, plus the canaries.
+    fn identities(
+        &mut self,
+        agent: &mut AgentConn,
+        msg: Vec<u8>,
+        record: &mut Record,
+    ) -> DynError<Vec<u8>> {
+        let domain = &self.domain;
+        let agent_id = &self.agent_socket;
+        let (reply, ids) = self.owners.update(|owned| {
+            let reply = agent.call(&msg)?;
+            let Ok(ids) = agent::parse_identities(&reply) else {
+                return Ok((reply, None));
+            };
+
+            // imported keys past their lifetime are gone from the agent
+            owned.retain(|o| o.agent != *agent_id || ids.iter().any(|id| id.blob == o.blob));
+            let ids: Vec<(Identity, Option<String>)> = ids.into_iter()
+                .map(|id| {
+                    let owned_by = owner(owned, &id.blob).map(str::to_string);
+                    (id, owned_by)
+                })
+                .collect();
+            return Ok((reply, Some(ids)));
+        })?;
+
+        let Some(ids) = ids else {
+            return Ok(reply);
+        };
+
+        let now = SystemTime::now();
+        let mut visible = Vec::new();
+        let mut stale_certs = 0u64;
+        for (id, owned_by) in &ids {
+            let foreign = owned_by.as_deref().is_some_and(|d| d != domain);
+            let selector = selector(&id.comment, owned_by.is_some());
+            let in_profile = self.argument_rules
+                .key_allowed(&keys::fingerprint(&id.blob), selector);
+            if foreign || !in_profile || self.locked(&id.blob, selector)? {
+                continue;
+            }
+            if !cert::current(&id.blob, now) {
+                stale_certs += 1;
+                continue;
+            }
+            visible.push((id.clone(), owned_by.is_some()));
+        }
+
+        record.set("keys", visible.len() as u64);
+        if stale_certs > 0 {
+            record.set("certs_hidden", stale_certs);
+        }
+        for canary in &self.canaries {
+            if !visible.iter().any(|(id, _)| id.blob == canary.blob) {
+                visible.push((canary.clone(), false));
+            }
+        }
+
+        let shown = self.present(&visible);
+        self.identities = Some(ids.into_iter().map(|(id, _)| id).collect());
+        return Ok(agent::encode_identities(&shown));
+    }
+
+    /// true for a key that needs unlocking in the vault and isn't
+    /// unlocked for this domain right now.
+    fn locked(&self, blob: &[u8], comment: Option<&str>) -> DynError<bool> {
+        let fingerprint = keys::fingerprint(blob);
+        if self.config.key_rules(&fingerprint, comment).require_unlock != Some(true) {
+            return Ok(false);
+        }
+
+        return Ok(!self.unlocks.is_unlocked(
+            &fingerprint, comment, &self.domain, SystemTime::now())?);
+    }
+
+    fn extension(
+        &mut self,
+        agent: &mut AgentConn,
+        msg: Vec<u8>,
+        record: &mut Record,
+    ) -> DynError<Vec<u8>> {
+        let mut reader = Reader::new(&msg[1..]);
+        let Ok(name) = reader.string() else {
+            return Ok(deny(record, "malformed request"));
+        };
+        let name = String::from_utf8_lossy(name).into_owned();
+        let contents = reader.rest();
+        record.set("extension", name.as_str());
+
+        let allowed = self.allowed_extensions();
+        if name == QUERY_EXT {
+            let mut reply = agent::success();
+            agent::put_string(&mut reply, QUERY_EXT);
+            for ext in allowed.iter().filter(|ext| !ext.contains(['*', '?'])) {
+                agent::put_string(&mut reply, ext);
+            }
+            #[cfg(feature = "builtin-agent")]
+            if self.ca.is_some() && self.domain_rules.cert_principals.is_some() {
+                agent::put_string(&mut reply, CERT_REQUEST_EXT);
+            }
+            return Ok(reply);
+        }
+
+        #[cfg(feature = "builtin-agent")]
+        if name == CERT_REQUEST_EXT {
+            return self.certify(contents, record);
+        }
+
+        if !allowed.iter().any(|ext| wildcard_match(ext, &name)) {
+            deny(record, "extension isn't allowed for this domain");
+            return Ok(vec![msg::EXTENSION_FAILURE]);
+        }
+
+        if name != SESSION_BIND_EXT {
+            return agent.call(&msg);
+        }
+
+        // the agent checks the host key signature, only binds it
+        // accepted make it into the session.
+        let Ok(bind) = Bind::parse(contents) else {
+            return Ok(deny(record, "malformed session-bind"));
+        };
+        let reply = agent.call(&msg)?;
+        if reply.first() == Some(&msg::SUCCESS) {
+            self.session.bind(bind);
+        }
+        return Ok(reply);
+    }
+
+    /// Issues a user certificate for a key of the client domain with the
+    /// principals asked for, or every one of its cert-principals if none
+    /// are.
+    #[cfg(feature = "builtin-agent")]
+    fn certify(&mut self, contents: &[u8], record: &mut Record) -> DynError<Vec<u8>> {
+        let Some(allowed) = &self.domain_rules.cert_principals else {
+            return Ok(deny(record, "domain may not request certificates"));
+        };
+        let Some(ca) = &mut self.ca else {
+            return Ok(deny(record, "the vault has no ca-key"));
+        };
+
+        let mut reader = Reader::new(contents);
+        let public_key = reader.str().ok()
+            .and_then(|key| PublicKey::from_openssh(key).ok());
+        let Some(public_key) = public_key else {
+            return Ok(deny(record, "malformed or unsupported key"));
+        };
+        let mut principals = Vec::new();
+        while !reader.is_empty() {
+            let Ok(principal) = reader.str() else {
+                return Ok(deny(record, "malformed request"));
+            };
+            principals.push(principal.to_string());
+        }
+
+        let blob = public_key.to_bytes()?;
+        record.set("key", keys::fingerprint(&blob));
+        record.set("key_type", keys::key_type(&blob));
+        record.set("principals", principals.join(","));
+        if !principals.iter().all(|principal| allowed.contains(principal)) {
+            return Ok(deny(record, "principal isn't in the domain's cert-principals"));
+        }
+        if principals.is_empty() {
+            principals = allowed.clone();
+            record.set("principals", principals.join(","));
+        }
+
+        let request = CertRequest {
+            public_key: &public_key,
+            key_id: match &self.argument {
+                Some(argument) => format!("{}+{argument}", self.domain),
+                None => self.domain.clone(),
+            },
+            principals: &principals,
+            validity: self.domain_rules.cert_validity.unwrap_or(CERT_VALIDITY),
+            options: self.domain_rules.cert_options.as_deref().unwrap_or_default(),
+        };
+        record.set("key_id", request.key_id.as_str());
+        record.set("validity", request.validity);
+
+        let cert = match ca.issue(&request, SystemTime::now()) {
+            Ok(Some(cert)) => cert,
+            Ok(None) => return Ok(deny(record, "CA key is locked")),
+            Err(e) => {
+                record.set("error", e.to_string());
+                return Ok(deny(record, "certificate couldn't be issued"));
+            }
+        };
+
+        record.set("serial", cert.serial());
+        record.set("ca", cert.signature_key().fingerprint(Default::default()).to_string());
+        record.set("valid_before", timestamp(cert.valid_before_time()));
+
+        let mut reply = agent::success();
+        agent::put_string(&mut reply, cert.to_openssh()?);
+        return Ok(reply);
+    }
+
+    /// Adds a key from the client domain to the vault agent, only for
+    /// domains with `import confirm` and once the vault user agreed. The
+    /// key always goes in with a lifetime and the confirm constraint.
+    fn import(
+        &mut self,
+        agent: &mut AgentConn,
+        msg: Vec<u8>,
+        record: &mut Record,
+    ) -> DynError<Vec<u8>> {
+        if self.domain_rules.import != Some(true) {
+            return Ok(deny(record, "domain may not add keys"));
+        }
+
+        let key = match NewKey::parse(&msg) {
+            Ok(key) => key,
+            Err(e) => {
+                record.set("error", e.to_string());
+                return Ok(deny(record, "malformed or unsupported key"));
+            }
+        };
+
+        let fingerprint = keys::fingerprint(&key.blob);
+        let lifetime = key.lifetime_within(
+            self.domain_rules.import_lifetime.unwrap_or(IMPORT_LIFETIME));
+        record.set("key", fingerprint.as_str());
+        record.set("comment", key.comment.as_str());
+        record.set("key_type", key.key_type.as_str());
+        record.set("bits", key.bits);
+        record.set("lifetime", lifetime);
+
+        let min_rsa_bits = self.domain_rules.import_min_rsa_bits
+            .unwrap_or(IMPORT_MIN_RSA_BITS);
+        if let Some(weakness) = key.weakness(min_rsa_bits) {
+            record.set("error", weakness);
+            return Ok(deny(record, "key is too weak"));
+        }
+
+        let what = format!(
+            "add {} key {fingerprint} ({}) to the vault agent for {}s",
+            escape(&key.key_type), escape(&key.comment), lifetime);
+        if !self.confirm(record, &what, &["key import"]) {
+            return Ok(deny(record, "not confirmed in the vault"));
+        }
+
+        self.identities = None;
+        let domain = &self.domain;
+        let agent_id = &self.agent_socket;
+        return self.owners.update(|owned| {
+            let ids = agent::parse_identities(&agent.call(&[msg::REQUEST_IDENTITIES])?)?;
+            let present = ids.iter().any(|id| id.blob == key.blob);
+            if present && owner(owned, &key.blob) != Some(domain) {
+                return Ok(deny(record, "key is already in the vault agent"));
+            }
+
+            let reply = agent.call(&key.constrained(lifetime))?;
+            if reply.first() == Some(&msg::SUCCESS) {
+                owned.retain(|o| o.blob != key.blob);
+                owned.push(Owned {
+                    domain: domain.clone(),
+                    blob: key.blob.clone(),
+                    agent: agent_id.clone(),
+                });
+            }
+            return Ok(reply);
+        });
+    }
+
+    /// Removes a key the client domain imported, keys of other domains
+    /// and of the vault can't be removed.
+    fn remove(
+        &mut self,
+        agent: &mut AgentConn,
+        msg: Vec<u8>,
+        record: &mut Record,
+    ) -> DynError<Vec<u8>> {
+        let Ok(blob) = Reader::new(&msg[1..]).string() else {
+            return Ok(deny(record, "malformed request"));
+        };
+        record.set("key", keys::fingerprint(blob));
+
+        self.identities = None;
+        let domain = &self.domain;
+        return self.owners.update(|owned| {
+            match owner(owned, blob) {
+                Some(d) if d == domain => (),
+                Some(_) => return Ok(deny(record, "key belongs to another domain")),
+                None => return Ok(deny(record, "vault keys can't be removed")),
+            }
+
+            let reply = agent.call(&msg)?;
+            if reply.first() == Some(&msg::SUCCESS) {
+                owned.retain(|o| o.blob != blob);
+            }
+            return Ok(reply);
+        });
+    }
+
+    /// REMOVE_ALL_IDENTITIES only removes the keys the client domain
+    /// imported itself.
+    fn remove_all(
+        &mut self,
+        agent: &mut AgentConn,
+        record: &mut Record,
+    ) -> DynError<Vec<u8>> {
+        self.identities = None;
+        let domain = &self.domain;
+        let agent_id = &self.agent_socket;
+        return self.owners.update(|owned| {
+            let mut removed = 0u64;
+            let mine = |o: &Owned| o.domain == *domain && o.agent == *agent_id;
+            for o in owned.iter().filter(|o| mine(o)) {
+                let mut request = vec![msg::REMOVE_IDENTITY];
+                agent::put_string(&mut request, &o.blob);
+                // a FAILURE is a key whose lifetime already ran out
+                if agent.call(&request)?.first() == Some(&msg::SUCCESS) {
+                    removed += 1;
+                }
+            }
+
+            owned.retain(|o| !mine(o));
+            record.set("removed", removed);
+            return Ok(agent::success());
+        });
+    }
+
+    /// extension names, or patterns, the client domain may use, only
+    /// session-bind@openssh.com unless configured.
+    fn allowed_extensions(&self) -> Vec<String> {
+        return self.domain_rules.extensions.clone()
+            .unwrap_or(vec![SESSION_BIND_EXT.to_string()]);
+    }
+
+    fn sign_request(
+        &mut self,
+        agent: &mut AgentConn,
+        msg: Vec<u8>,
+        record: &mut Record,
+    ) -> DynError<Vec<u8>> {
+        let mut reader = Reader::new(&msg[1..]);
+        let (Ok(blob), Ok(data), Ok(mut flags)) =
+            (reader.string(), reader.string(), reader.u32())
+        else {
+            return Ok(deny(record, "malformed request"));
+        };
+
+        let fingerprint = keys::fingerprint(blob);
+        let comment = self.comment(agent, blob)?;
+        record.set("key", fingerprint.as_str());
+        record.set("comment", comment.clone());
+        record.set("flags", flags);
+        record.set("sig_alg", keys::sig_alg(blob, flags));
+
+        let owned_by = self.owners.update(|owned| Ok(owner(owned, blob).map(str::to_string)))?;
+        if owned_by.as_deref().is_some_and(|d| d != self.domain) {
+            return Ok(deny(record, "key belongs to another domain"));
+        }
+        let selector = comment.as_deref().and_then(|c| selector(c, owned_by.is_some()));
+
+        let payload = Payload::classify(data);
+        record.set("payload", payload.class());
+        if let Payload::Userauth(ref userauth) = payload {
+            record.set("user", userauth.user.as_str());
+            record.set("service", userauth.service.as_str());
+            record.set("auth_alg", userauth.alg.as_str());
+        }
+
+        if let Some(canary) = self.canaries.iter().find(|c| c.blob == blob) {
+            record.set("canary", true);
+            let what = self.describe(&payload, &canary.comment);
+            alert::raise(
+                self.config.alert_command.as_deref(),
+                "canary",
+                &self.domain,
+                &format!("tried to {what} ({fingerprint})"));
+            return Ok(deny(record, "canary key"));
+        }
+
+        if !self.argument_rules.key_allowed(&fingerprint, selector) {
+            return Ok(deny(record, "key isn't in the service argument's key profile"));
+        }
+
+        if self.locked(blob, selector)? {
+            return Ok(deny(record, "key is locked, unlock it in the vault"));
+        }
+
+        match Cert::parse(blob) {
+            Ok(None) => (),
+            Ok(Some(cert)) => {
+                record.set("cert_serial", cert.serial);
+                record.set("cert_key_id", cert.key_id.as_str());
+                if let Err(reason) = cert.check(&payload, SystemTime::now()) {
+                    return Ok(deny(record, reason));
+                }
+            }
+            Err(_) => return Ok(deny(record, "malformed certificate")),
+        }
+
+        let rules = self.config.key_rules(&fingerprint, selector);
+        let checked = policy::check_dest(
+            &rules, &self.session, &self.known_hosts, &payload);
+
+        if let Err(reason) = checked {
+            return Ok(deny(record, reason));
+        }
+
+        if keys::sig_alg(blob, flags) == "ssh-rsa" {
+            record.set("legacy_sha1", true);
+            self.log_legacy_sha1(&payload, &fingerprint);
+
+            match rules.rsa_sha1.unwrap_or(RsaSha1::Deny) {
+                RsaSha1::Allow => (),
+                RsaSha1::Deny => return Ok(deny(
+                    record, "RSA SHA-1 signatures are refused")),
+                RsaSha1::Upgrade => {
+                    flags |= RSA_SHA2_512;
+                    record.set("sig_alg", keys::sig_alg(blob, flags));
+                }
+            }
+        }
+
+        let mut confirm = Vec::new();
+        match policy::sign_action(&rules, &payload) {
+            Action::Allow => (),
+            Action::Deny => return Ok(deny(
+                record, "key may not sign this kind of data")),
+            Action::Confirm => confirm.push("sign rule"),
+        }
+
+        match policy::forwarding_action(&rules, &self.session) {
+            Action::Allow => (),
+            Action::Deny => return Ok(deny(
+                record, "key may not be used through agent forwarding")),
+            Action::Confirm => confirm.push("through a forwarded agent"),
+        }
+
+        // the signature is counted before it is made and taken back if it
+        // isn't, so concurrent requests see each other's signatures
+        let now = SystemTime::now();
+        let checked = self.usage.reserve(
+            &fingerprint, comment.as_deref(), &self.domain, now,
+            |key_today, domain_today| {
+                record.set("key_today", key_today);
+                record.set("domain_today", domain_today);
+
+                let mut over = Vec::new();
+                let quotas = [
+                    (rules.daily_quota, key_today,
+                        "key's daily quota is used up", "over the key's daily quota"),
+                    (self.domain_rules.daily_quota, domain_today,
+                        "domain's daily quota is used up", "over the domain's daily quota"),
+                ];
+                for (quota, used, denied, confirmed) in quotas {
+                    let Some(quota) = quota.filter(|quota| used >= quota.limit) else {
+                        continue;
+                    };
+                    match quota.action {
+                        Action::Deny => return Err(denied),
+                        _ => over.push(confirmed),
+                    }
+                }
+                return Ok(over);
+            })?;
+        match checked {
+            Ok(over) => confirm.extend(over),
+            Err(denied) => return Ok(deny(record, denied)),
+        }
+
+        if !confirm.is_empty() {
+            let key = comment.as_deref().unwrap_or(&fingerprint);
+            let what = self.describe(&payload, key);
+            if !self.confirm(record, &what, &confirm) {
+                self.usage.release(&fingerprint, &self.domain, now)?;
+                return Ok(deny(record, "not confirmed in the vault"));
+            }
+        }
+
+        let mut request = vec![msg::SIGN_REQUEST];
+        agent::put_string(&mut request, blob);
+        agent::put_string(&mut request, data);
+        agent::put_u32(&mut request, flags);
+        let reply = match agent.call(&request) {
+            Ok(reply) if reply.first() == Some(&msg::SIGN_RESPONSE) => reply,
+            reply => {
+                self.usage.release(&fingerprint, &self.domain, now)?;
+                return reply;
+            }
+        };
+        self.flag_anomalies(record, &fingerprint, comment.as_deref())?;
+        return Ok(reply);
+    }
+
+    /// Checks a signature that was made against the baseline, raises an
+    /// alert for every anomaly and lists them in the record.
+    fn flag_anomalies(
+        &mut self,
+        record: &mut Record,
+        fingerprint: &str,
+        comment: Option<&str>,
+    ) -> DynError<()> {
+        self.baseline.catch_up(&self.audit)?;
+        let kinds = self.config.anomaly_alerts.clone()
+            .unwrap_or(anomaly::KINDS.to_vec());
+        let ts = record.get_str("ts").unwrap_or_default().to_string();
+        let dest = self.session.dest_fingerprint();
+        let dest_name = self.session.dest().map(|bind| self.host_name(&bind.host_key));
+
+        let anomalies = self.baseline.check(&KeyUse {
+            ts: &ts,
+            key: fingerprint,
+            key_name: comment.unwrap_or(fingerprint),
+            domain: &self.domain,
+            dest: dest.as_deref(),
+            dest_name: dest_name.as_deref(),
+        }, &kinds);
+        if anomalies.is_empty() {
+            return Ok(());
+        }
+
+        let kinds: Vec<&str> = anomalies.iter().map(|anomaly| anomaly.kind).collect();
+        record.set("anomalies", kinds.join(","));
+        for anomaly in anomalies {
+            alert::raise(
+                self.config.alert_command.as_deref(),
+                anomaly.kind,
+                &self.domain,
+                &anomaly.msg);
+        }
+        return Ok(());
+    }
+
+    /// Servers still asking for ssh-rsa signatures go into their own log so
+    /// they can be chased up.
+    fn log_legacy_sha1(&self, payload: &Payload, fingerprint: &str) {
+        let dest = self.session.dest()
+            .map(|bind| self.host_name(&bind.host_key))
+            .unwrap_or("unbound".to_string());
+
+        let user = match payload {
+            Payload::Userauth(userauth) => format!("{}@", escape(&userauth.user)),
+            _ => String::new(),
+        };
+
+        append(
+            &format!("{} {} {user}{dest} {fingerprint}\n",
+                timestamp(SystemTime::now()), self.domain),
+            LEGACY_SHA1_FNAME,
+            ERR_LOG_DIR_NAME);
+    }
+
+    /// The identities as the client domain gets to see them, with the
+    /// comments rewritten according to its `comments` setting. Each
+    /// identity comes with whether a client domain imported it.
+    fn present(&self, ids: &[(Identity, bool)]) -> Vec<Identity> {
+        let mode = self.domain_rules.comments.unwrap_or(Comments::Keep);
+        let vault_name = self.config.vault_name.clone()
+            .or_else(|| fs::read_to_string(HOSTNAME_PATH).ok())
+            .map(|name| name.trim().to_string())
+            .unwrap_or_default();
+
+        return ids.iter().map(|(id, imported)| {
+            let comment = match mode {
+                Comments::Keep => id.comment.clone(),
+                Comments::Strip => String::new(),
+                Comments::Prefix => format!("{vault_name}:{}", id.comment),
+                Comments::Alias => self.config
+                    .key_rules(&keys::fingerprint(&id.blob), selector(&id.comment, *imported))
+                    .alias
+                    .unwrap_or_default(),
+            };
+            Identity { blob: id.blob.clone(), comment }
+        }).collect();
+    }
+
+    /// What a sign request does, in words for a confirmation prompt or
+    /// an alert. What the client sent is escaped.
+    fn describe(&self, payload: &Payload, key: &str) -> String {
+        let dest = match self.session.dest() {
+            Some(bind) => self.host_name(&bind.host_key),
+            None => "an unverified host".to_string(),
+        };
+
+        match payload {
+            Payload::Userauth(userauth) => return format!(
+                "log in as {}@{dest} with {} key {}",
+                escape(&userauth.user), escape(&userauth.alg), escape(key)),
+
+            Payload::Sshsig { namespace } => return format!(
+                "sign {} data (SSHSIG) with key {}", escape(namespace), escape(key)),
+
+            Payload::Unknown => return format!(
+                "sign unrecognised data with key {}", escape(key)),
+        }
+    }
+
+    /// Asks the vault user whether the client domain may do what, the
+    /// reasons being why confirmation is needed. The answer goes into
+    /// the record.
+    fn confirm(&self, record: &mut Record, what: &str, reasons: &[&str]) -> bool {
+        let msg = format!(
+            "Allow {} to {what}? ({})", self.domain, reasons.join(", "));
+        let confirmed = prompt::confirm(self.config.askpass.as_deref(), &msg);
+        record.set("confirmed", confirmed);
+        return confirmed;
+    }
+
+    /// first known_hosts name of a host key, else its fingerprint.
+    fn host_name(&self, host_key: &[u8]) -> String {
+        return self.known_hosts.names_for(host_key).into_iter().next()
+            .unwrap_or_else(|| keys::fingerprint(host_key));
+    }
+
+    /// comment of the key from the agent's identity list, asks the
+    /// agent for the list if the client didn't do so on this connection.
+    fn comment(
+        &mut self,
+        agent: &mut AgentConn,
+        blob: &[u8],
+    ) -> DynError<Option<String>> {
+        if self.identities.is_none() {
+            let reply = agent.call(&[msg::REQUEST_IDENTITIES])?;
+            self.identities = agent::parse_identities(&reply).ok();
+        }
+
+        return Ok(self.identities.iter()
+            .flatten()
+            .find(|id| id.blob == blob)
+            .map(|id| id.comment.clone()));
+    }
+}
+
+/// The comment `key` blocks and key profiles may select a key by. Client
+/// domains choose the comments of the keys they import, so those are
+/// only ever selected by fingerprint or `*`.
+fn selector(comment: &str, imported: bool) -> Option<&str> {
+    return if imported { None } else { Some(comment) };
+}
+
+/// marks the record as denied and returns the reply for the client.
+fn deny(record: &mut Record, reason: &str) -> Vec<u8> {
+    record.set("decision", "deny");
+    record.set("reason", reason);
+    return agent::failure();
+}
+
+impl AgentFilter for Vault {
+    fn request(&mut self, agent: &mut AgentConn, msg: Vec<u8>) -> DynError<Vec<u8>> {
+        let start = Instant::now();
+        let msg_type = *msg.first().unwrap_or(&0);
+        let mut record = Record::new(&self.domain, msg::name(msg_type));
+        if let Some(ref argument) = self.argument {
+            record.set("argument", argument.as_str());
+        }
+
+        let was_killed = self.killed;
+        self.killed = kill::engaged(&self.kill_path);
+        let reply = if self.killed {
+            let reply = deny(&mut record, "kill switch engaged");
+            if !was_killed {
+                // forget the session and drop the agent connection along
+                // with the session binds the agent holds for it. An agent
+                // that is already gone took its binds with it.
+                self.reconnect();
+                if let Err(e) = agent.reconnect() {
+                    record.set("error", e.to_string());
+                }
+                record.set("sessions_dropped", true);
+            }
+            Ok(reply)
+        } else {
+            self.dispatch(agent, msg, &mut record)
+        };
+
+        record.set("dest", self.session.dest_fingerprint());
+        record.set("dest_host", self.session.dest()
+            .map(|bind| self.known_hosts.names_for(&bind.host_key).join(","))
+            .filter(|names| !names.is_empty()));
+        record.set("session", self.session.dest_session());
+        record.set("forwarded", self.session.forwarded());
+        if let Some(via) = self.session.forwarded_via() {
+            record.set("forwarded_via", self.host_name(&via.host_key));
+        }
+
+        // requests that fail on the vault's side are recorded before the
+        // error ends the connection
+        match &reply {
+            Ok(reply) => {
+                if record.get("decision").is_none() {
+                    record.set("decision", "allow");
+                }
+                record.set("result", msg::name(*reply.first().unwrap_or(&0)));
+            }
+            Err(e) => {
+                record.set("decision", "error");
+                record.set("error", e.to_string());
+            }
+        }
+        record.set("latency_us", start.elapsed().as_micros() as u64);
+        self.audit.write(record)?;
+        return reply;
+    }
+
+    fn reconnect(&mut self) {
+        self.identities = None;
+        self.session = Session::default();
+    }
+}
@@ -0,0 +1,171 @@
+use super::Vault;
+use crate::{
+    audit::Record,
+    config::{Config, Comments},
+    kill::NoAgent,
+    owners::Owned,
+    testing::TempDir,
+};
+use socket_stdinout::{
+    agent::{self, msg, put_string, Identity},
+    filter::{AgentFilter, AgentBackend, AgentConn},
+    types::DynError,
+};
+use std::{
+    fs,
+    os::unix::{fs::PermissionsExt, net::UnixStream},
+    time::Duration,
+};
+use anyhow::anyhow;
+
+/// An agent that has gone away: nothing reaches it and it can't be
+/// reconnected to.
+struct GoneAgent;
+
+impl AgentBackend for GoneAgent {
+    fn call(&mut self, _msg: &[u8]) -> DynError<Vec<u8>> {
+        return Err(anyhow!("Error: agent is gone").into());
+    }
+
+    fn reconnect(&mut self) -> DynError<()> {
+        return Err(anyhow!("Error: agent is gone").into());
+    }
+}
+
+/// An agent holding a fixed set of keys.
+struct KeysAgent(Vec<Identity>);
+
+impl AgentBackend for KeysAgent {
+    fn call(&mut self, _msg: &[u8]) -> DynError<Vec<u8>> {
+        return Ok(agent::encode_identities(&self.0));
+    }
+
+    fn reconnect(&mut self) -> DynError<()> {
+        return Ok(());
+    }
+}
+
+fn vault_in(name: &str) -> (Vault, TempDir) {
+    let dir = TempDir::new(&format!("vault-{name}"));
+    return (Vault::with_state_dir("work", dir.path()), dir);
+}
+
+fn records(dir: &TempDir) -> Vec<Record> {
+    return fs::read_to_string(dir.join("audit.log")).unwrap()
+        .lines()
+        .map(|line| Record::parse(line).unwrap())
+        .collect();
+}
+
+#[test]
+fn kill_switch_denies_and_audits_with_the_agent_gone() {
+    let (mut vault, dir) = vault_in("kill");
+    let mut agent = AgentConn::with_backend(Box::new(GoneAgent));
+    fs::write(dir.join("killed"), "incident\n").unwrap();
+
+    for _ in 0..2 {
+        let reply = vault.request(&mut agent, vec![msg::REQUEST_IDENTITIES]).unwrap();
+        assert_eq!(reply, agent::failure());
+    }
+
+    let records = records(&dir);
+    assert_eq!(records.len(), 2);
+    for record in &records {
+        assert_eq!(record.get_str("decision"), Some("deny"));
+        assert_eq!(record.get_str("reason"), Some("kill switch engaged"));
+        assert_eq!(record.get_str("result"), Some("FAILURE"));
+    }
+    // the sessions are only dropped when the switch is first seen
+    assert!(records[0].get_str("error").is_some());
+    assert!(records[0].get("sessions_dropped").is_some());
+    assert!(records[1].get("sessions_dropped").is_none());
+}
+
+#[test]
+fn killed_vault_refuses_without_an_agent() {
+    let (_, dir) = vault_in("killed");
+    fs::write(dir.join("killed"), "incident\n").unwrap();
+    let mut vault = Vault::with_state_dir("work", dir.path());
+    assert!(vault.killed());
+    let mut agent = AgentConn::with_backend(Box::new(NoAgent));
+
+    let reply = vault.request(&mut agent, vec![msg::REQUEST_IDENTITIES]).unwrap();
+    assert_eq!(reply, agent::failure());
+    let record = records(&dir).remove(0);
+    assert_eq!(record.get_str("reason"), Some("kill switch engaged"));
+    assert!(record.get("error").is_none());
+
+    // released while connected, the request can't go anywhere
+    fs::remove_file(dir.join("killed")).unwrap();
+    assert!(vault.request(&mut agent, vec![msg::REQUEST_IDENTITIES]).is_err());
+    assert_eq!(records(&dir)[1].get_str("decision"), Some("error"));
+}
+
+#[test]
+fn failed_requests_are_audited() {
+    let (mut vault, dir) = vault_in("error");
+    // an agent that never answers
+    let (stream, _agent_end) = UnixStream::pair().unwrap();
+    stream.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+    let mut agent = AgentConn::new(stream, None);
+
+    assert!(vault.request(&mut agent, vec![msg::REQUEST_IDENTITIES]).is_err());
+
+    let log = fs::read_to_string(dir.join("audit.log")).unwrap();
+    assert_eq!(log.lines().count(), 1);
+    assert!(log.contains(r#""decision":"error","error":"#), "{log}");
+}
+
+#[test]
+fn imported_key_comments_are_escaped_in_the_prompt() {
+    let (mut vault, dir) = vault_in("import");
+    let mut agent = AgentConn::with_backend(Box::new(GoneAgent));
+    // an askpass that keeps the prompt and refuses
+    let (askpass, prompt) = (dir.join("askpass"), dir.join("prompt"));
+    fs::write(&askpass, format!("#!/bin/sh\nprintf %s \"$1\" > {prompt}\nexit 1\n")).unwrap();
+    fs::set_permissions(&askpass, fs::Permissions::from_mode(0o755)).unwrap();
+    vault.config.askpass = Some(askpass);
+    vault.domain_rules.import = Some(true);
+
+    let mut request = vec![msg::ADD_IDENTITY];
+    put_string(&mut request, "ssh-ed25519");
+    put_string(&mut request, [1u8; 32]);
+    put_string(&mut request, [2u8; 64]);
+    put_string(&mut request, "laptop\nAllow work to do nothing? (yes)");
+    let reply = vault.request(&mut agent, request).unwrap();
+    assert_eq!(reply, agent::failure());
+
+    let prompt = fs::read_to_string(prompt).unwrap();
+    assert!(!prompt.contains('\n'), "{prompt}");
+    assert!(prompt.contains("(laptop\\nAllow work to do nothing? (yes))"), "{prompt}");
+}
+
+#[test]
+fn imported_keys_arent_selected_by_comment() {
+    let (mut vault, _dir) = vault_in("selector");
+    let key = |byte| {
+        let mut blob = Vec::new();
+        put_string(&mut blob, "ssh-ed25519");
+        put_string(&mut blob, [byte; 32]);
+        return Identity { blob, comment: "deploy".to_string() };
+    };
+    let (vault_key, imported) = (key(1), key(2));
+    let mut agent = AgentConn::with_backend(Box::new(
+        KeysAgent(vec![vault_key.clone(), imported.clone()])));
+    vault.config = Config::parse("key deploy\nalias deploy-key\n", "/config").unwrap();
+    vault.domain_rules.comments = Some(Comments::Alias);
+    vault.owners.update(|owned| {
+        owned.push(Owned {
+            domain: "work".to_string(),
+            blob: imported.blob.clone(),
+            agent: vault.agent_socket.clone(),
+        });
+        return Ok(());
+    }).unwrap();
+
+    let reply = vault.request(&mut agent, vec![msg::REQUEST_IDENTITIES]).unwrap();
+    let shown = agent::parse_identities(&reply).unwrap();
+    assert_eq!(shown.len(), 2);
+    assert_eq!(shown[0].comment, "deploy-key");
+    assert_eq!(shown[1].comment, "");
+}